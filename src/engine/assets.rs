@@ -0,0 +1,108 @@
+use std::{collections::BTreeMap, fs, path::Path};
+
+use log::{debug, info};
+use walkdir::WalkDir;
+
+use crate::engine::{config::SiteConfig, error::EngineError, util::content_hash};
+
+/// The manifest of static files, written to the build directory.
+const MANIFEST_FILE: &str = "asset-manifest.json";
+/// How many characters of the content hash go in a fingerprinted file name.
+const FINGERPRINT_LEN: usize = 8;
+
+/// Static files, by their path relative to the static directory,
+/// mapped to their path in the build directory.
+pub(crate) type AssetManifest = BTreeMap<String, String>;
+
+/// Copy the static files, layered over the theme's, into the build directory.
+///
+/// Files with an extension listed in `[assets] fingerprint` get their content hash in
+/// their name, e.g. `css/base.css` becomes `css/base.3f9a2c1d.css`, so they can be
+/// cached forever. Every file is recorded in the manifest.
+pub(crate) fn copy_static_files(site_config: &SiteConfig) -> Result<AssetManifest, EngineError> {
+    // The site's static files replace the theme's with the same path.
+    let mut files = BTreeMap::new();
+    for dir in site_config.static_dirs() {
+        for entry in WalkDir::new(&dir).into_iter().filter_map(|e| e.ok()) {
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let name = entry
+                .path()
+                .strip_prefix(&dir)?
+                .to_string_lossy()
+                .replace('\\', "/");
+            files.insert(name, entry.path().to_path_buf());
+        }
+    }
+
+    let mut manifest = AssetManifest::new();
+    for (name, path) in files {
+        write_asset(site_config, &mut manifest, &name, &fs::read(&path)?)?;
+        debug!("Copied static file {}", path.display());
+    }
+    info!(
+        "Copied {} static files, {} fingerprinted",
+        manifest.len(),
+        manifest
+            .iter()
+            .filter(|(name, output)| name != output)
+            .count()
+    );
+
+    Ok(manifest)
+}
+
+/// Write a static file, generated or copied, into the build directory and record it in `manifest`.
+pub(crate) fn write_asset(
+    site_config: &SiteConfig,
+    manifest: &mut AssetManifest,
+    name: &str,
+    content: &[u8],
+) -> Result<(), EngineError> {
+    let output = if fingerprinted(site_config, name) {
+        fingerprint(name, &content_hash(content))
+    } else {
+        name.to_string()
+    };
+
+    let build_path = Path::new(&site_config.build_path).join(&output);
+    if let Some(parent) = build_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&build_path, content)?;
+    manifest.insert(name.to_string(), output);
+
+    Ok(())
+}
+
+/// Write the manifest to the build directory, for other tools.
+pub(crate) fn write_manifest(
+    site_config: &SiteConfig,
+    manifest: &AssetManifest,
+) -> Result<(), EngineError> {
+    let manifest_json = serde_json::to_string_pretty(manifest).expect("a map of strings");
+    fs::write(
+        Path::new(&site_config.build_path).join(MANIFEST_FILE),
+        manifest_json,
+    )?;
+
+    Ok(())
+}
+
+/// Whether a static file is configured to be fingerprinted.
+fn fingerprinted(site_config: &SiteConfig, name: &str) -> bool {
+    Path::new(name)
+        .extension()
+        .and_then(|s| s.to_str())
+        .is_some_and(|ext| site_config.assets.fingerprint.iter().any(|e| e == ext))
+}
+
+/// Put a content hash before a file's extension, e.g. `css/base.3f9a2c1d.css`.
+fn fingerprint(name: &str, hash: &str) -> String {
+    let hash = &hash[..FINGERPRINT_LEN];
+    match name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.ends_with('/') => format!("{stem}.{hash}.{ext}"),
+        _ => format!("{name}.{hash}"),
+    }
+}
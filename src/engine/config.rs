@@ -1,4 +1,8 @@
-use std::{fs, path::PathBuf};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
 
 use log::info;
 use serde::{Deserialize, Serialize};
@@ -13,15 +17,280 @@ pub(crate) struct SiteConfig {
     pub(crate) title: String,
     /// The default description for the site.
     pub(crate) description: String,
-    /// The content directory path, relative to the project's root.
+    /// The site's author, credited in structured data.
+    pub(crate) author: String,
+    /// The content directory path. Like every other path, it's relative to the configuration file.
     pub(crate) content_path: String,
-    /// The build directory path, relative to the project's root.
+    /// The build directory path.
     pub(crate) build_path: String,
+    /// The templates directory path.
+    #[serde(default = "default_templates_path")]
+    pub(crate) templates_path: String,
+    /// The static files directory path. Its files are served as they are, from the site's root.
+    #[serde(default = "default_static_path")]
+    pub(crate) static_path: String,
+    /// A theme directory, with `templates` and `static` subdirectories, to share across sites.
+    /// The site's own templates and static files take precedence over the theme's.
+    pub(crate) theme_path: Option<String>,
+    /// How static files are copied into the build directory.
+    #[serde(default)]
+    pub(crate) assets: AssetConfig,
+    /// How stylesheets are bundled.
+    #[serde(default)]
+    pub(crate) css: CssConfig,
+    /// A Content Security Policy allowing only the inline scripts and styles the engine emits.
+    /// No policy is emitted if absent.
+    pub(crate) csp: Option<CspConfig>,
+    /// Whether to minify every HTML page written to the build directory.
+    #[serde(default)]
+    pub(crate) minify_html: bool,
+    /// The syntax highlighting theme, a TextMate `.tmTheme` file.
+    #[serde(default = "default_highlight_theme")]
+    pub(crate) highlight_theme: String,
+    /// The data directory path. Its TOML, JSON and CSV files
+    /// are available to templates as `data.<name>`.
+    #[serde(default = "default_data_path")]
+    pub(crate) data_path: String,
+    /// The quotes data file path.
+    pub(crate) quotes_path: String,
+    /// How the quote in every page's footer is picked.
+    #[serde(default)]
+    pub(crate) quote_mode: QuoteMode,
+    /// The web server to generate configuration snippets for, if any.
+    pub(crate) server: Option<ServerKind>,
+    /// Redirects from old paths to new paths (e.g. `"/blog/old/" = "/blog/new/"`).
+    #[serde(default)]
+    pub(crate) redirects: BTreeMap<String, String>,
+    /// The image processing pipeline. Images are only copied if absent.
+    pub(crate) images: Option<ImageConfig>,
+    /// Social media preview images for blog posts. No previews are generated if absent.
+    pub(crate) social: Option<SocialConfig>,
+    /// How to render sidenotes and marginnotes. Pages can override it.
+    #[serde(default)]
+    pub(crate) notes: NoteStyle,
+    /// A search index for the `/search/` page. No index is written if absent.
+    pub(crate) search: Option<SearchConfig>,
+    /// How many paragraphs make a page's summary when it has no `<!-- more -->` marker.
+    /// If absent, only pages with a marker get a summary.
+    pub(crate) summary_paragraphs: Option<usize>,
+    /// Which headings make it into the table of contents. Pages can override it.
+    #[serde(default)]
+    pub(crate) toc: TocConfig,
+}
+
+impl SiteConfig {
+    /// The template directories, from the lowest precedence to the highest.
+    pub(crate) fn template_dirs(&self) -> Vec<PathBuf> {
+        self.layers("templates", &self.templates_path)
+    }
+
+    /// The static files directories, from the lowest precedence to the highest.
+    pub(crate) fn static_dirs(&self) -> Vec<PathBuf> {
+        self.layers("static", &self.static_path)
+    }
+
+    /// Find a template file, looking in the site's templates before the theme's.
+    pub(crate) fn find_template(&self, name: &str) -> Option<PathBuf> {
+        self.template_dirs()
+            .into_iter()
+            .rev()
+            .map(|dir| dir.join(name))
+            .find(|path| path.is_file())
+    }
+
+    /// The theme's `subdir`, if there's a theme, followed by the site's own directory.
+    fn layers(&self, subdir: &str, site_dir: &str) -> Vec<PathBuf> {
+        let mut dirs = Vec::new();
+        if let Some(theme) = &self.theme_path {
+            dirs.push(Path::new(theme).join(subdir));
+        }
+        dirs.push(PathBuf::from(site_dir));
+        dirs
+    }
+
+    /// Resolve every path relative to `base`, the configuration file's directory,
+    /// so the engine can run from anywhere.
+    fn resolve_paths(&mut self, base: &Path) {
+        let mut paths = vec![
+            &mut self.content_path,
+            &mut self.build_path,
+            &mut self.templates_path,
+            &mut self.static_path,
+            &mut self.highlight_theme,
+            &mut self.data_path,
+            &mut self.quotes_path,
+        ];
+        paths.extend(self.theme_path.as_mut());
+        if let Some(images) = &mut self.images {
+            paths.push(&mut images.cache_path);
+        }
+        if let Some(social) = &mut self.social {
+            paths.extend([
+                &mut social.banner,
+                &mut social.font_path,
+                &mut social.cache_path,
+            ]);
+        }
+
+        for path in paths {
+            let relative = Path::new(path.as_str());
+            if relative.is_relative() {
+                let relative = relative.strip_prefix("./").unwrap_or(relative);
+                *path = base.join(relative).display().to_string();
+            }
+        }
+    }
+}
+
+/// Configuration for copying static files.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub(crate) struct AssetConfig {
+    /// The extensions of static files to fingerprint with their content hash,
+    /// e.g. `["css", "js"]`. Templates get their URLs from `asset(path="...")`.
+    #[serde(default)]
+    pub(crate) fingerprint: Vec<String>,
+}
+
+/// Configuration for bundling stylesheets with the templates' `<style>` blocks.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub(crate) struct CssConfig {
+    /// Stylesheets in the static directory's `css/`, bundled in this order,
+    /// followed by the templates' styles.
+    #[serde(default)]
+    pub(crate) bundle: Vec<String>,
+    /// Stylesheets whose `@font-face` rules are only needed by pages with math.
+    #[serde(default)]
+    pub(crate) math: Vec<String>,
+}
+
+/// Configuration for the Content Security Policy and other security headers.
+///
+/// Every page gets the policy as a `<meta>` tag, with hashes of its inline scripts and
+/// styles. If a server is configured, the policy for every page and the other headers
+/// are also written as a snippet for it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct CspConfig {
+    /// Directives and their sources, e.g. `"img-src" = "'self' data:"`.
+    /// `script-src` and `style-src` default to `'self'`, and get the hashes.
+    pub(crate) directives: BTreeMap<String, String>,
+    /// Other security headers, e.g. `"X-Content-Type-Options" = "nosniff"`.
+    #[serde(default)]
+    pub(crate) headers: BTreeMap<String, String>,
+}
+
+/// Configuration for the client-side search index.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct SearchConfig {
+    /// The sections whose pages are indexed, e.g. `["/blog/"]`.
+    /// Pages can opt in or out with `search = true` or `search = false`.
+    pub(crate) sections: Vec<String>,
+}
+
+/// Depth controls for the table of contents.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct TocConfig {
+    /// The shallowest heading level to include. `2` excludes the page's own `# Title`.
+    pub(crate) min_depth: u8,
+    /// The deepest heading level to include.
+    pub(crate) max_depth: u8,
+}
+
+impl Default for TocConfig {
+    fn default() -> Self {
+        Self {
+            min_depth: 2,
+            max_depth: 6,
+        }
+    }
+}
+
+/// How sidenotes and marginnotes are rendered.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum NoteStyle {
+    /// In the margin, next to the text referencing them.
+    #[default]
+    Margin,
+    /// As numbered footnotes at the end of the page, for feeds and print.
+    Footnotes,
+}
+
+/// How the quote in the footer is picked.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum QuoteMode {
+    /// By a script on every page load. Readers without JavaScript see the daily quote.
+    #[default]
+    Random,
+    /// At build time, seeded by the page's path and the build date. No script is emitted.
+    Daily,
+}
+
+/// Configuration for resizing and re-encoding images co-located with pages.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct ImageConfig {
+    /// The widths, in pixels, to generate for every image.
+    /// Widths larger than the original image are skipped.
+    pub(crate) widths: Vec<u32>,
+    /// Modern formats to generate alongside the original format.
+    /// WebP is only generated for PNG sources, as it's lossless.
+    #[serde(default)]
+    pub(crate) formats: Vec<ImageFormat>,
+    /// The value of the `sizes` attribute emitted with every `srcset`.
+    pub(crate) sizes: String,
+    /// Where generated variants are cached, keyed by content hash.
+    pub(crate) cache_path: String,
+}
+
+/// Configuration for rendering social media preview images.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct SocialConfig {
+    /// The banner image shown at the top of every preview.
+    pub(crate) banner: String,
+    /// A directory of fonts available to the preview template.
+    pub(crate) font_path: String,
+    /// Where rendered previews are cached, keyed by content hash.
+    pub(crate) cache_path: String,
+}
+
+/// The modern image formats the pipeline can encode to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum ImageFormat {
+    Webp,
+    Avif,
+}
+
+/// The web servers the engine can generate configuration snippets for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum ServerKind {
+    /// Netlify-style `_redirects` files, also understood by Cloudflare Pages.
+    Netlify,
+    /// Caddyfile snippets, to be `import`ed from the site block.
+    Caddy,
+}
+
+fn default_templates_path() -> String {
+    "./src/templates".to_string()
+}
+
+fn default_static_path() -> String {
+    "./assets".to_string()
+}
+
+fn default_highlight_theme() -> String {
+    "./src/themes/gruvbox-dark.tmTheme".to_string()
+}
+
+fn default_data_path() -> String {
+    "./data".to_string()
 }
 
 pub(crate) fn parse_config_file(config_path: PathBuf) -> Result<SiteConfig, EngineError> {
-    let config_raw: String = fs::read_to_string(config_path)?;
-    let config: SiteConfig = toml::from_str(&config_raw)?;
+    let config_raw: String = fs::read_to_string(&config_path)?;
+    let mut config: SiteConfig = toml::from_str(&config_raw)?;
+    config.resolve_paths(config_path.parent().unwrap_or(Path::new("")));
 
     info!("Succesfully read configuration file");
 
@@ -0,0 +1,202 @@
+use std::{collections::BTreeSet, fs, path::Path};
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use log::info;
+use regex::Regex;
+use sha2::{Digest, Sha256};
+use walkdir::WalkDir;
+
+use crate::engine::{
+    config::{CspConfig, ServerKind, SiteConfig},
+    error::EngineError,
+};
+
+/// Directives that browsers ignore in a `<meta>` policy.
+const HEADER_ONLY_DIRECTIVES: &[&str] = &["frame-ancestors", "report-uri", "report-to", "sandbox"];
+
+/// The SHA-256 hashes of the inline scripts, styles and style attributes in some HTML.
+#[derive(Debug, Default)]
+pub(crate) struct InlineHashes {
+    scripts: BTreeSet<String>,
+    styles: BTreeSet<String>,
+    style_attrs: BTreeSet<String>,
+}
+
+impl InlineHashes {
+    /// Hash every inline script (except data blocks, like JSON-LD), `<style>` block and `style` attribute.
+    pub(crate) fn new(html: &str) -> Result<Self, EngineError> {
+        let script_rgx = Regex::new(r"(?is)<script\b([^>]*)>(.*?)</script>")?;
+        let script_type_rgx = Regex::new(r#"(?i)\btype\s*=\s*"([^"]*)""#)?;
+        let style_rgx = Regex::new(r"(?is)<style\b[^>]*>(.*?)</style>")?;
+        let style_attr_rgx = Regex::new(r#"(?i)\sstyle\s*=\s*"([^"]*)""#)?;
+
+        let mut hashes = Self::default();
+        for caps in script_rgx.captures_iter(html) {
+            let executable = script_type_rgx
+                .captures(&caps[1])
+                .is_none_or(|t| matches!(&t[1], "text/javascript" | "module"));
+            if executable && !caps[1].contains("src=") {
+                hashes.scripts.insert(csp_hash(&caps[2]));
+            }
+        }
+        for caps in style_rgx.captures_iter(html) {
+            hashes.styles.insert(csp_hash(&caps[1]));
+        }
+        // Only search tags for `style` attributes, not the contents of scripts and styles.
+        let markup = script_rgx.replace_all(html, "");
+        let markup = style_rgx.replace_all(&markup, "");
+        for caps in style_attr_rgx.captures_iter(&markup) {
+            hashes
+                .style_attrs
+                .insert(csp_hash(&html_escape::decode_html_entities(&caps[1])));
+        }
+
+        Ok(hashes)
+    }
+
+    fn extend(&mut self, other: Self) {
+        self.scripts.extend(other.scripts);
+        self.styles.extend(other.styles);
+        self.style_attrs.extend(other.style_attrs);
+    }
+}
+
+/// Build the policy, adding `hashes` to `script-src` and `style-src`. Those default
+/// to `'self'`, and `style-src` gets `'unsafe-hashes'` if there are style attributes.
+fn build_policy(csp: &CspConfig, hashes: &InlineHashes, meta: bool) -> String {
+    let mut directives = csp.directives.clone();
+    for (directive, sources) in [
+        ("script-src", &hashes.scripts),
+        ("style-src", &hashes.styles),
+    ] {
+        let value = directives
+            .entry(directive.to_string())
+            .or_insert_with(|| "'self'".to_string());
+        if directive == "style-src" && !hashes.style_attrs.is_empty() {
+            value.push_str(" 'unsafe-hashes'");
+            for hash in &hashes.style_attrs {
+                value.push_str(&format!(" '{hash}'"));
+            }
+        }
+        for hash in sources {
+            value.push_str(&format!(" '{hash}'"));
+        }
+    }
+
+    directives
+        .iter()
+        .filter(|(directive, _)| !(meta && HEADER_ONLY_DIRECTIVES.contains(&directive.as_str())))
+        .map(|(directive, sources)| format!("{directive} {sources}"))
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+/// Add a `<meta>` policy for a page's own inline scripts and styles, right after its charset.
+pub(crate) fn add_csp_meta(csp: &CspConfig, html: &str) -> Result<String, EngineError> {
+    let policy = build_policy(csp, &InlineHashes::new(html)?, true);
+    let meta = format!(
+        "<meta http-equiv=\"Content-Security-Policy\" content=\"{}\" />",
+        html_escape::encode_double_quoted_attribute(&policy)
+    );
+
+    let charset_rgx = Regex::new(r"(?i)<meta charset[^>]*>")?;
+    let head_rgx = Regex::new(r"(?i)<head\b[^>]*>")?;
+    Ok(
+        match charset_rgx.find(html).or_else(|| head_rgx.find(html)) {
+            Some(anchor) => format!("{}{meta}{}", &html[..anchor.end()], &html[anchor.end()..]),
+            None => html.to_string(),
+        },
+    )
+}
+
+/// Write the policy, allowing every page's inline scripts and styles, and the
+/// other security headers as a server configuration snippet, if a server is configured.
+pub(crate) fn write_security_headers(
+    site_config: &SiteConfig,
+    build_dir: &str,
+) -> Result<(), EngineError> {
+    let (Some(csp), Some(server)) = (&site_config.csp, site_config.server) else {
+        return Ok(());
+    };
+
+    let mut hashes = InlineHashes::default();
+    for entry in WalkDir::new(build_dir).into_iter().filter_map(|e| e.ok()) {
+        if entry.path().extension().and_then(|s| s.to_str()) == Some("html") {
+            hashes.extend(InlineHashes::new(&fs::read_to_string(entry.path())?)?);
+        }
+    }
+
+    let mut headers = vec![(
+        "Content-Security-Policy".to_string(),
+        build_policy(csp, &hashes, false),
+    )];
+    headers.extend(csp.headers.clone());
+
+    let (file_name, snippet) = match server {
+        ServerKind::Netlify => (
+            "_headers",
+            format!(
+                "/*\n{}",
+                headers
+                    .iter()
+                    .map(|(name, value)| format!("  {name}: {value}\n"))
+                    .collect::<String>()
+            ),
+        ),
+        ServerKind::Caddy => (
+            "headers.caddy",
+            format!(
+                "header {{\n{}}}\n",
+                headers
+                    .iter()
+                    .map(|(name, value)| format!("\t{name} \"{}\"\n", value.replace('"', "\\\"")))
+                    .collect::<String>()
+            ),
+        ),
+    };
+
+    let snippet_path = Path::new(build_dir).join(file_name);
+    fs::write(&snippet_path, snippet)?;
+    info!(
+        "Wrote {} security headers to {}",
+        headers.len(),
+        snippet_path.display()
+    );
+
+    Ok(())
+}
+
+/// A CSP source for some inline content: its base64-encoded SHA-256 hash.
+fn csp_hash(content: &str) -> String {
+    format!(
+        "sha256-{}",
+        STANDARD.encode(Sha256::digest(content.as_bytes()))
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_csp_meta() {
+        let csp = CspConfig {
+            directives: [("default-src", "'self'"), ("frame-ancestors", "'none'")]
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .into(),
+            headers: Default::default(),
+        };
+        let html = "<html><head><meta charset=\"utf-8\" /><script type=\"application/ld+json\">{}</script></head><body><p style=\"color:red;\">Hi</p><script>alert(1)</script></body></html>";
+
+        let html = add_csp_meta(&csp, html).unwrap();
+        assert!(html.starts_with(
+            "<html><head><meta charset=\"utf-8\" /><meta http-equiv=\"Content-Security-Policy\" content=\"default-src 'self'; script-src 'self' 'sha256-"
+        ));
+        assert!(html.contains(&format!(
+            "style-src 'self' 'unsafe-hashes' '{}'\" />",
+            csp_hash("color:red;")
+        )));
+        assert!(html.contains(&format!("script-src 'self' '{}';", csp_hash("alert(1)"))));
+        assert!(!html.contains("frame-ancestors"));
+    }
+}
@@ -0,0 +1,287 @@
+use std::{collections::HashSet, fs, path::Path};
+
+use log::info;
+use regex::Regex;
+
+use crate::engine::{
+    assets::{AssetManifest, write_asset},
+    config::SiteConfig,
+    error::EngineError,
+};
+
+/// The bundled stylesheet. It's next to the stylesheets it bundles, so their relative `url()`s still work.
+const BUNDLE: &str = "css/bundle.css";
+/// The bundled stylesheet for pages without math, without the math fonts.
+const BUNDLE_NO_MATH: &str = "css/bundle.nomath.css";
+
+/// The URLs of the bundled stylesheets.
+pub(crate) struct CssBundles {
+    full: String,
+    no_math: String,
+}
+
+impl CssBundles {
+    /// The stylesheet for a page, depending on whether it has math.
+    pub(crate) fn stylesheet(&self, math: bool) -> &str {
+        if math { &self.full } else { &self.no_math }
+    }
+}
+
+/// Remove the `<style>` blocks from templates, given as `(name, source)` pairs,
+/// and return their CSS, minified and without duplicate rules. The last copy of
+/// a rule is kept, so it still overrides the rules before it.
+///
+/// Every extracted block becomes site-wide: it goes into the bundle every page loads,
+/// including the blocks of partials only some pages include, and it isn't scoped to
+/// its template. Its selectors must therefore be specific enough to apply only where
+/// they're meant to (`.blog-list`, not `ul`).
+///
+/// Blocks with `Tera` syntax are left in place, and so are those of templates that
+/// extend another, which are only rendered if they're inside a `{% block %}`.
+pub(crate) fn extract_template_styles(
+    sources: &mut [(String, String)],
+) -> Result<String, EngineError> {
+    let style_rgx = Regex::new(r"(?s)[ \t]*<style[^>]*>(.*?)</style>[ \t]*\n?")?;
+    let extends_rgx = Regex::new(r"\{%-?\s*extends\s")?;
+
+    let mut css = String::new();
+    for (name, source) in sources.iter_mut() {
+        if extends_rgx.is_match(source) {
+            continue;
+        }
+        let mut extracted = 0;
+        *source = style_rgx
+            .replace_all(source, |caps: &regex::Captures| {
+                if caps[1].contains("{{") || caps[1].contains("{%") {
+                    return caps[0].to_string();
+                }
+                extracted += 1;
+                css.push_str(&minify_css(&caps[1]));
+                String::new()
+            })
+            .to_string();
+        if extracted > 0 {
+            info!("Extracted {extracted} <style> block(s) from {name}");
+        }
+    }
+
+    let mut seen = HashSet::new();
+    let mut rules: Vec<&str> = split_rules(&css)
+        .into_iter()
+        .rev()
+        .filter(|rule| seen.insert(*rule))
+        .collect();
+    rules.reverse();
+
+    Ok(rules.concat())
+}
+
+/// Bundle the stylesheets in `[css] bundle` and the templates' CSS into a single
+/// minified stylesheet, plus one without the `@font-face` rules of the stylesheets
+/// in `[css] math`, for pages without math. Both are recorded in `manifest`.
+pub(crate) fn bundle_css(
+    site_config: &SiteConfig,
+    template_css: &str,
+    manifest: &mut AssetManifest,
+) -> Result<CssBundles, EngineError> {
+    let mut full = String::new();
+    let mut no_math = String::new();
+    let mut charset = None;
+    for name in &site_config.css.bundle {
+        let output = manifest
+            .get(name)
+            .ok_or_else(|| EngineError::InvalidPath(format!("no stylesheet at {name}")))?;
+        let css = minify_css(&fs::read_to_string(
+            Path::new(&site_config.build_path).join(output),
+        )?);
+        let math = site_config.css.math.contains(name);
+
+        for rule in split_rules(&css) {
+            // `@charset` is only valid at the very start of a stylesheet.
+            if rule.starts_with("@charset") {
+                charset.get_or_insert(rule.to_string());
+                continue;
+            }
+            full.push_str(rule);
+            if !(math && rule.starts_with("@font-face")) {
+                no_math.push_str(rule);
+            }
+        }
+    }
+    full.push_str(template_css);
+    no_math.push_str(template_css);
+
+    let charset = charset.unwrap_or_default();
+    write_asset(
+        site_config,
+        manifest,
+        BUNDLE,
+        format!("{charset}{full}").as_bytes(),
+    )?;
+    write_asset(
+        site_config,
+        manifest,
+        BUNDLE_NO_MATH,
+        format!("{charset}{no_math}").as_bytes(),
+    )?;
+    info!(
+        "Bundled {} stylesheets and the templates' styles into {BUNDLE} ({} bytes, {} without math)",
+        site_config.css.bundle.len(),
+        charset.len() + full.len(),
+        charset.len() + no_math.len()
+    );
+
+    Ok(CssBundles {
+        full: format!("/{}", manifest[BUNDLE]),
+        no_math: format!("/{}", manifest[BUNDLE_NO_MATH]),
+    })
+}
+
+/// Minify CSS: strip comments and every whitespace that isn't needed.
+fn minify_css(css: &str) -> String {
+    let mut minified = String::with_capacity(css.len());
+    let mut chars = css.chars().peekable();
+    let mut space = false;
+
+    while let Some(c) = chars.next() {
+        match c {
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut last = ' ';
+                for c in chars.by_ref() {
+                    if last == '*' && c == '/' {
+                        break;
+                    }
+                    last = c;
+                }
+                space = true;
+            }
+            c if c.is_whitespace() => space = true,
+            _ => {
+                // Spaces before `:` are kept, as they're meaningful in selectors (`a :hover`).
+                if space
+                    && !minified.is_empty()
+                    && !minified.ends_with(['{', '}', ';', ',', ':', '>'])
+                    && !matches!(c, '{' | '}' | ';' | ',' | '>')
+                {
+                    minified.push(' ');
+                }
+                space = false;
+                if c == '}' && minified.ends_with(';') {
+                    minified.pop();
+                }
+                minified.push(c);
+
+                // Strings are kept as they are.
+                if c == '"' || c == '\'' {
+                    let mut escaped = false;
+                    for s in chars.by_ref() {
+                        minified.push(s);
+                        if s == c && !escaped {
+                            break;
+                        }
+                        escaped = s == '\\' && !escaped;
+                    }
+                }
+            }
+        }
+    }
+
+    minified
+}
+
+/// Split minified CSS into its top-level rules and statements.
+fn split_rules(css: &str) -> Vec<&str> {
+    let mut rules = Vec::new();
+    let mut start = 0;
+    let mut depth = 0;
+    let mut quote = None;
+    let mut escaped = false;
+
+    for (i, c) in css.char_indices() {
+        if let Some(q) = quote {
+            if c == q && !escaped {
+                quote = None;
+            }
+            escaped = c == '\\' && !escaped;
+            continue;
+        }
+        match c {
+            '"' | '\'' => quote = Some(c),
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    rules.push(&css[start..=i]);
+                    start = i + 1;
+                }
+            }
+            ';' if depth == 0 => {
+                rules.push(&css[start..=i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    if !css[start..].trim().is_empty() {
+        rules.push(&css[start..]);
+    }
+
+    rules
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_minify_css() {
+        let css = "@charset \"UTF-8\";\n/* A comment. */\nbody {\n    font-family: \"Book  Antiqua\", serif;\n    width: calc(50% + 0.3em);\n}\n\n.toc nav > a :hover { color: red; }\n@media screen and (max-width: 540px) {\n    section { margin-top: 18.75vh; }\n}\n";
+        let minified = minify_css(css);
+
+        assert_eq!(
+            minified,
+            "@charset \"UTF-8\";body{font-family:\"Book  Antiqua\",serif;width:calc(50% + 0.3em)}.toc nav>a :hover{color:red}@media screen and (max-width:540px){section{margin-top:18.75vh}}"
+        );
+        assert_eq!(
+            split_rules(&minified),
+            [
+                "@charset \"UTF-8\";",
+                "body{font-family:\"Book  Antiqua\",serif;width:calc(50% + 0.3em)}",
+                ".toc nav>a :hover{color:red}",
+                "@media screen and (max-width:540px){section{margin-top:18.75vh}}",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_extract_template_styles() {
+        let mut sources = [
+            (
+                "a.html",
+                "<style>\n  .a { color: red; }\n</style>\n<p>A</p>",
+            ),
+            (
+                "b.html",
+                "<style>.a{color:red}.b{color:blue}</style><p>B</p>",
+            ),
+            ("c.html", "<style>.c { width: {{ w }}px; }</style>"),
+            (
+                "d.html",
+                "{% extends \"base.html\" %}<style>h1 { font-weight: bold; }</style>",
+            ),
+            (
+                "e.html",
+                "<style>.a { color: green; } .a { color: red; }</style>",
+            ),
+        ]
+        .map(|(name, source)| (name.to_string(), source.to_string()));
+
+        let css = extract_template_styles(&mut sources).unwrap();
+        // `.a{color:red}` comes last, so it still overrides `.a{color:green}`.
+        assert_eq!(css, ".b{color:blue}.a{color:green}.a{color:red}");
+        assert_eq!(sources[0].1, "<p>A</p>");
+        assert_eq!(sources[1].1, "<p>B</p>");
+        assert!(sources[2].1.contains("<style>") && sources[3].1.contains("<style>"));
+    }
+}
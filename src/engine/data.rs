@@ -0,0 +1,106 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use log::info;
+use serde_json::{Map, Value};
+use tera::Function;
+
+use crate::engine::error::EngineError;
+
+/// Load every TOML, JSON and CSV file in `data_dir` into a map keyed by file stem,
+/// e.g. `data/netops.toml` becomes `data.netops` in templates.
+pub(crate) fn load_data_dir(data_dir: &str) -> Result<Map<String, Value>, EngineError> {
+    let mut data = Map::new();
+    if !Path::new(data_dir).exists() {
+        return Ok(data);
+    }
+
+    let mut entries = fs::read_dir(data_dir)?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .collect::<Vec<_>>();
+    entries.sort();
+
+    for path in entries {
+        let Some(name) = path.file_stem().and_then(|s| s.to_str()) else {
+            continue;
+        };
+        if let Some(value) = load_data_file(&path)? {
+            data.insert(name.to_string(), value);
+        }
+    }
+    info!("Loaded {} data files from {data_dir}", data.len());
+
+    Ok(data)
+}
+
+/// Load a single data file, or `None` if it's not TOML, JSON or CSV.
+fn load_data_file(path: &Path) -> Result<Option<Value>, EngineError> {
+    let parse_error =
+        |e: &dyn std::fmt::Display| EngineError::Data(format!("{}: {e}", path.display()));
+
+    let value = match path.extension().and_then(|s| s.to_str()) {
+        Some("toml") => toml::from_str(&fs::read_to_string(path)?)?,
+        Some("json") => {
+            serde_json::from_str(&fs::read_to_string(path)?).map_err(|e| parse_error(&e))?
+        }
+        // CSV files become an array of objects keyed by the header row.
+        Some("csv") => {
+            let mut reader = csv::Reader::from_path(path).map_err(|e| parse_error(&e))?;
+            let headers = reader.headers().map_err(|e| parse_error(&e))?.clone();
+            let mut rows = Vec::new();
+            for record in reader.records() {
+                let record = record.map_err(|e| parse_error(&e))?;
+                let row = headers
+                    .iter()
+                    .zip(record.iter())
+                    .map(|(header, field)| (header.to_string(), Value::String(field.to_string())))
+                    .collect::<Map<_, _>>();
+                rows.push(Value::Object(row));
+            }
+            Value::Array(rows)
+        }
+        _ => return Ok(None),
+    };
+
+    Ok(Some(value))
+}
+
+/// The `load_data(path="...")` `Tera` function, which loads a data file
+/// relative to the data directory.
+pub(crate) struct LoadData {
+    data_dir: PathBuf,
+}
+
+impl LoadData {
+    pub(crate) fn new(data_dir: &str) -> Self {
+        Self {
+            data_dir: PathBuf::from(data_dir),
+        }
+    }
+}
+
+impl Function for LoadData {
+    fn call(&self, args: &HashMap<String, Value>) -> tera::Result<Value> {
+        let path = args
+            .get("path")
+            .and_then(Value::as_str)
+            .ok_or("`load_data` requires a `path` argument")?;
+        if Path::new(path).is_absolute() || path.contains("..") {
+            return Err(format!("`load_data` can't read {path} outside the data directory").into());
+        }
+
+        match load_data_file(&self.data_dir.join(path)) {
+            Ok(Some(value)) => Ok(value),
+            Ok(None) => Err(format!("`load_data` can't read {path}: unknown format").into()),
+            Err(e) => Err(format!("`load_data` failed to read {path}: {e}").into()),
+        }
+    }
+
+    fn is_safe(&self) -> bool {
+        false
+    }
+}
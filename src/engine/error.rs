@@ -22,4 +22,25 @@ pub(crate) enum EngineError {
 
     #[error("Regex erro: {0}")]
     Regex(#[from] regex::Error),
+
+    #[error("Redirect error: {0}")]
+    Redirect(String),
+
+    #[error("Image error: {0}")]
+    Image(#[from] image::ImageError),
+
+    #[error("Social preview error: {0}")]
+    Preview(String),
+
+    #[error("Quote error: {0}")]
+    Quote(String),
+
+    #[error("Data error: {0}")]
+    Data(String),
+
+    #[error("Shortcode error: {0}")]
+    Shortcode(String),
+
+    #[error("Template error: {0}")]
+    Template(String),
 }
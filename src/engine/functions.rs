@@ -0,0 +1,233 @@
+use std::{collections::HashMap, sync::Arc};
+
+use chrono::NaiveDate;
+use pulldown_cmark::{Parser, html};
+use serde_json::{Value, json};
+use tera::{Filter, Function, Tera, to_value, try_get_value};
+
+use crate::engine::{
+    assets::AssetManifest,
+    config::SiteConfig,
+    markdown::{MD_OPTIONS, PageMetadata, process_katex},
+    stats::reading_time,
+    util::{clean_url, slugify},
+};
+
+/// The default format of the `date_format` filter, e.g. `March 19, 2024`.
+const DEFAULT_DATE_FORMAT: &str = "%B %-d, %Y";
+
+/// Register the engine's functions and filters on `tera`.
+///
+/// `pages` is the site-wide page index, used to look up other pages,
+/// and `assets` the static files copied into the build directory.
+pub(crate) fn register_functions(
+    tera: &mut Tera,
+    site_config: &SiteConfig,
+    pages: Vec<PageMetadata>,
+    assets: AssetManifest,
+) {
+    let pages = Arc::new(pages);
+
+    tera.register_function(
+        "get_page",
+        GetPage {
+            pages: pages.clone(),
+        },
+    );
+    tera.register_function("get_section", GetSection { pages });
+    tera.register_function(
+        "url",
+        Url {
+            cname: site_config.cname.clone(),
+        },
+    );
+    tera.register_function(
+        "asset",
+        Asset {
+            manifest: Arc::new(assets),
+        },
+    );
+
+    tera.register_filter("date_format", date_format);
+    tera.register_filter("slugify", slugify_filter);
+    tera.register_filter("reading_time", reading_time_filter);
+    tera.register_filter("markdown", MarkdownFilter);
+}
+
+/// Normalize a page path, either a URL (`/blog/post/`) or a content path
+/// (`blog/post/index.md`), into the clean URL used in the page index.
+fn normalize_path(path: &str) -> String {
+    let path = format!("/{}", path.trim_start_matches('/'));
+    let path = if let Some(dir) = path.strip_suffix("index.md") {
+        dir.to_string()
+    } else if let Some(file) = path.strip_suffix(".md") {
+        format!("{file}.html")
+    } else {
+        path
+    };
+
+    clean_url(&path)
+}
+
+/// Get a required string argument of a function or filter.
+fn string_arg<'a>(args: &'a HashMap<String, Value>, name: &str, of: &str) -> tera::Result<&'a str> {
+    args.get(name)
+        .and_then(Value::as_str)
+        .ok_or_else(|| format!("`{of}` requires a `{name}` string argument").into())
+}
+
+/// `get_page(path="blog/post/index.md")`: another page's metadata.
+struct GetPage {
+    pages: Arc<Vec<PageMetadata>>,
+}
+
+impl Function for GetPage {
+    fn call(&self, args: &HashMap<String, Value>) -> tera::Result<Value> {
+        let path = normalize_path(string_arg(args, "path", "get_page")?);
+        let page = self
+            .pages
+            .iter()
+            .find(|page| page.path.as_deref() == Some(path.as_str()))
+            .ok_or_else(|| format!("`get_page`: there's no page at {path}"))?;
+
+        Ok(to_value(page)?)
+    }
+}
+
+/// `get_section(path="blog")`: a section's index page, if any, and
+/// every page under it, newest first.
+struct GetSection {
+    pages: Arc<Vec<PageMetadata>>,
+}
+
+impl Function for GetSection {
+    fn call(&self, args: &HashMap<String, Value>) -> tera::Result<Value> {
+        let path = string_arg(args, "path", "get_section")?;
+        let path = format!("{}/", normalize_path(path).trim_end_matches('/'));
+
+        let index = self
+            .pages
+            .iter()
+            .find(|page| page.path.as_deref() == Some(path.as_str()));
+        let mut pages = self
+            .pages
+            .iter()
+            .filter(|page| {
+                page.path
+                    .as_deref()
+                    .is_some_and(|p| p != path && p.starts_with(&path))
+            })
+            .collect::<Vec<_>>();
+        if index.is_none() && pages.is_empty() {
+            return Err(format!("`get_section`: there's no section at {path}").into());
+        }
+        pages.sort_by_key(|page| std::cmp::Reverse(page.date));
+
+        Ok(json!({"path": path, "index": index, "pages": pages}))
+    }
+}
+
+/// `url(path="/blog/", absolute=true)`: a site URL, optionally with the scheme and host.
+struct Url {
+    cname: String,
+}
+
+impl Function for Url {
+    fn call(&self, args: &HashMap<String, Value>) -> tera::Result<Value> {
+        let path = normalize_path(string_arg(args, "path", "url")?);
+        let absolute = args
+            .get("absolute")
+            .and_then(Value::as_bool)
+            .unwrap_or(false);
+
+        Ok(if absolute {
+            Value::String(format!("https://{}{path}", self.cname))
+        } else {
+            Value::String(path)
+        })
+    }
+
+    fn is_safe(&self) -> bool {
+        true
+    }
+}
+
+/// `asset(path="css/base.css")`: a static file's URL, fingerprinted
+/// with its content hash if configured, so browsers can cache it forever.
+struct Asset {
+    manifest: Arc<AssetManifest>,
+}
+
+impl Function for Asset {
+    fn call(&self, args: &HashMap<String, Value>) -> tera::Result<Value> {
+        let path = string_arg(args, "path", "asset")?.trim_start_matches('/');
+        let output = self
+            .manifest
+            .get(path)
+            .ok_or_else(|| format!("`asset`: there's no static file at {path}"))?;
+
+        Ok(Value::String(format!("/{output}")))
+    }
+
+    fn is_safe(&self) -> bool {
+        true
+    }
+}
+
+/// `{{ page.date | date_format(format="%Y") }}`: format an ISO 8601 date.
+fn date_format(value: &Value, args: &HashMap<String, Value>) -> tera::Result<Value> {
+    let date = try_get_value!("date_format", "value", String, value);
+    let format = args
+        .get("format")
+        .and_then(Value::as_str)
+        .unwrap_or(DEFAULT_DATE_FORMAT);
+    let date = NaiveDate::parse_from_str(&date, "%Y-%m-%d")
+        .map_err(|e| format!("`date_format`: can't parse {date}: {e}"))?;
+
+    Ok(Value::String(date.format(format).to_string()))
+}
+
+/// `{{ title | slugify }}`: the same slugifier as heading IDs.
+fn slugify_filter(value: &Value, _: &HashMap<String, Value>) -> tera::Result<Value> {
+    let text = try_get_value!("slugify", "value", String, value);
+
+    Ok(Value::String(slugify(&text)))
+}
+
+/// `{{ text | reading_time }}`: the estimated reading time of some text, in minutes.
+fn reading_time_filter(value: &Value, _: &HashMap<String, Value>) -> tera::Result<Value> {
+    let text = try_get_value!("reading_time", "value", String, value);
+    let words = text.split_whitespace().count();
+
+    Ok(to_value(reading_time(words))?)
+}
+
+/// `{{ snippet | markdown(inline=true) }}`: render Markdown and `LaTeX` into HTML.
+struct MarkdownFilter;
+
+impl Filter for MarkdownFilter {
+    fn filter(&self, value: &Value, args: &HashMap<String, Value>) -> tera::Result<Value> {
+        let markdown = try_get_value!("markdown", "value", String, value);
+        let inline = args.get("inline").and_then(Value::as_bool).unwrap_or(false);
+
+        let markdown = process_katex(&markdown).map_err(|e| e.to_string())?;
+        let mut html_content = String::new();
+        html::push_html(&mut html_content, Parser::new_ext(&markdown, MD_OPTIONS));
+
+        let html_content = html_content.trim();
+        let html_content = if inline {
+            html_content
+                .strip_prefix("<p>")
+                .and_then(|html| html.strip_suffix("</p>"))
+                .unwrap_or(html_content)
+        } else {
+            html_content
+        };
+
+        Ok(Value::String(html_content.to_string()))
+    }
+
+    fn is_safe(&self) -> bool {
+        true
+    }
+}
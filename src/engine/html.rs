@@ -0,0 +1,158 @@
+use std::{fs, path::Path};
+
+use log::info;
+use regex::Regex;
+
+use crate::engine::{config::SiteConfig, csp::add_csp_meta, error::EngineError};
+
+/// Elements around which whitespace is never rendered.
+const BLOCK_TAGS: &[&str] = &[
+    "!doctype",
+    "article",
+    "aside",
+    "blockquote",
+    "body",
+    "br",
+    "details",
+    "div",
+    "figcaption",
+    "figure",
+    "footer",
+    "form",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "head",
+    "header",
+    "hr",
+    "html",
+    "li",
+    "link",
+    "main",
+    "meta",
+    "nav",
+    "noscript",
+    "ol",
+    "p",
+    "pre",
+    "script",
+    "section",
+    "style",
+    "summary",
+    "table",
+    "tbody",
+    "td",
+    "tfoot",
+    "th",
+    "thead",
+    "title",
+    "tr",
+    "ul",
+];
+
+/// Write a page into the build directory, with its Content Security Policy
+/// and minified if configured.
+pub(crate) fn write_html(
+    site_config: &SiteConfig,
+    build_path: &Path,
+    html: &str,
+) -> Result<(), EngineError> {
+    if let Some(parent) = build_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let html = match &site_config.csp {
+        Some(csp) => add_csp_meta(csp, html)?,
+        None => html.to_string(),
+    };
+    if !site_config.minify_html {
+        fs::write(build_path, html)?;
+        return Ok(());
+    }
+
+    let minified = minify_html(&html)?;
+    info!(
+        "Minified {}: {} -> {} bytes ({:.1}% smaller)",
+        build_path.display(),
+        html.len(),
+        minified.len(),
+        100.0 * (html.len() - minified.len()) as f64 / html.len().max(1) as f64
+    );
+    fs::write(build_path, minified)?;
+
+    Ok(())
+}
+
+/// Minify HTML: remove comments and collapse whitespace, dropping it next to
+/// block elements, where it's never rendered. The contents of `<pre>`, `<textarea>`,
+/// `<script>` and `<style>` are kept as they are, as are tags and their attributes.
+fn minify_html(html: &str) -> Result<String, EngineError> {
+    let token_rgx = Regex::new(
+        r"(?is)<!--.*?-->|<pre\b.*?</pre>|<textarea\b.*?</textarea>|<script\b.*?</script>|<style\b.*?</style>|<[^>]*>",
+    )?;
+    let whitespace_rgx = Regex::new(r"\s+")?;
+
+    // Alternate text and tokens, text first, so `tokens[i]` is followed by `texts[i + 1]`.
+    let mut texts = Vec::new();
+    let mut tokens = Vec::new();
+    let mut last = 0;
+    for token in token_rgx.find_iter(html) {
+        texts.push(&html[last..token.start()]);
+        tokens.push(token.as_str());
+        last = token.end();
+    }
+    texts.push(&html[last..]);
+
+    let mut minified = String::with_capacity(html.len());
+    for (i, text) in texts.iter().enumerate() {
+        let text = whitespace_rgx.replace_all(text, " ");
+        let mut text = text.as_ref();
+        // Text on both sides of a removed comment would make a double space.
+        if i == 0 || is_block(tokens[i - 1]) || minified.ends_with(' ') {
+            text = text.trim_start();
+        }
+        if i == tokens.len() || is_block(tokens[i]) {
+            text = text.trim_end();
+        }
+        minified.push_str(text);
+
+        if let Some(token) = tokens.get(i)
+            && !token.starts_with("<!--")
+        {
+            minified.push_str(token);
+        }
+    }
+
+    Ok(minified)
+}
+
+/// Whether a token opens or closes a block element.
+fn is_block(token: &str) -> bool {
+    let name = token
+        .trim_start_matches('<')
+        .trim_start_matches('/')
+        .split(|c: char| c.is_whitespace() || c == '>' || c == '/')
+        .next()
+        .unwrap_or_default()
+        .to_lowercase();
+
+    BLOCK_TAGS.contains(&name.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_minify_html() {
+        let html = "<!doctype html>\n<html>\n    <head>\n        <title>A  page</title>\n    </head>\n    <body>\n        <!-- A comment. -->\n        <p>Some <!-- another --> <em>text</em>\n        and <span class=\"katex\"><span>x</span> <span>=</span></span> math.</p>\n        <pre><code>fn main() {\n    ok\n}</code></pre>\n        <script>let a  =  1;</script>\n    </body>\n</html>\n";
+
+        assert_eq!(
+            minify_html(html).unwrap(),
+            "<!doctype html><html><head><title>A page</title></head><body><p>Some <em>text</em> and <span class=\"katex\"><span>x</span> <span>=</span></span> math.</p><pre><code>fn main() {\n    ok\n}</code></pre><script>let a  =  1;</script></body></html>"
+        );
+    }
+}
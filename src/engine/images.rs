@@ -0,0 +1,449 @@
+use std::{
+    fs,
+    io::BufWriter,
+    path::{Path, PathBuf},
+};
+
+use image::{
+    DynamicImage, ImageReader,
+    codecs::{
+        avif::AvifEncoder,
+        png::{CompressionType, FilterType as PngFilterType, PngEncoder},
+        webp::WebPEncoder,
+    },
+    imageops::FilterType,
+};
+use log::{debug, info, warn};
+
+use crate::engine::{
+    config::{ImageConfig, ImageFormat},
+    error::EngineError,
+    util::content_hash,
+};
+
+/// AVIF encoder speed, from 1 (slowest) to 10 (fastest).
+const AVIF_SPEED: u8 = 6;
+/// AVIF encoder quality, from 1 to 100.
+const AVIF_QUALITY: u8 = 70;
+
+/// Resizes and re-encodes images co-located with pages,
+/// caching the generated variants by the source's content hash and encoder settings.
+pub(crate) struct ImagePipeline {
+    config: Option<ImageConfig>,
+}
+
+/// An image after going through the [`ImagePipeline`].
+pub(crate) struct ProcessedImage {
+    /// The image's original `src`.
+    pub(crate) src: String,
+    /// The original width, in pixels.
+    pub(crate) width: u32,
+    /// The original height, in pixels.
+    pub(crate) height: u32,
+    /// The `srcset` for the image's original format.
+    srcset: Option<String>,
+    /// The `srcset`s for modern formats, keyed by MIME type.
+    sources: Vec<(&'static str, String)>,
+    /// The value of the `sizes` attribute.
+    sizes: Option<String>,
+}
+
+/// A source image being processed, decoded the first time a variant needs encoding.
+struct SourceImage<'a> {
+    path: &'a Path,
+    /// The image's `src`, relative to its page.
+    src: &'a str,
+    hash: String,
+    /// The source file's size, in bytes.
+    size: u64,
+    decoded: Option<DynamicImage>,
+}
+
+/// A single variant to be generated from a source image.
+enum Variant {
+    /// The source image's own format.
+    Original(image::ImageFormat),
+    /// One of the modern formats from [`ImageConfig`].
+    Modern(ImageFormat),
+}
+
+impl ImagePipeline {
+    pub(crate) fn new(config: Option<ImageConfig>) -> Result<Self, EngineError> {
+        if let Some(config) = &config {
+            fs::create_dir_all(&config.cache_path)?;
+            info!("Using image cache at {}", config.cache_path);
+        }
+
+        Ok(Self { config })
+    }
+
+    /// Process an image referenced as `src` by a page living in `source_dir`,
+    /// writing generated variants to the page's `build_dir`.
+    ///
+    /// Returns `None` for remote images, absolute paths, and missing files.
+    pub(crate) fn process(
+        &self,
+        src: &str,
+        source_dir: &Path,
+        build_dir: &Path,
+    ) -> Result<Option<ProcessedImage>, EngineError> {
+        if src.contains("://") || src.starts_with('/') || src.starts_with("data:") {
+            return Ok(None);
+        }
+
+        let source_path = source_dir.join(src);
+        if !source_path.exists() {
+            warn!("Image {} does not exist", source_path.display());
+            return Ok(None);
+        }
+
+        let (width, height) = image::image_dimensions(&source_path)?;
+        let mut processed = ProcessedImage {
+            src: src.to_string(),
+            width,
+            height,
+            srcset: None,
+            sources: Vec::new(),
+            sizes: None,
+        };
+
+        // Animated GIFs would lose their animation, so they are left untouched.
+        let original_format = image::ImageFormat::from_path(&source_path)?;
+        let config = match &self.config {
+            Some(config) if original_format != image::ImageFormat::Gif => config,
+            _ => return Ok(Some(processed)),
+        };
+
+        let content = fs::read(&source_path)?;
+        let mut source = SourceImage {
+            path: &source_path,
+            src,
+            hash: content_hash(&content),
+            size: content.len() as u64,
+            decoded: None,
+        };
+
+        // The original itself already serves the full width in its own format.
+        let smaller_widths: Vec<u32> = config
+            .widths
+            .iter()
+            .copied()
+            .filter(|w| *w < width)
+            .collect();
+        let mut all_widths = smaller_widths.clone();
+        all_widths.push(width);
+
+        // Widths are generated from the largest down, and each candidate must be
+        // smaller than the larger ones, or it would only make the page heavier.
+        let mut srcset = Vec::new();
+        let mut max_size = source.size;
+        for w in smaller_widths.iter().rev() {
+            let variant = Variant::Original(original_format);
+            if let Some((url, size)) =
+                self.variant(&mut source, build_dir, *w, &variant, max_size)?
+            {
+                srcset.push(format!("{url} {w}w"));
+                max_size = size;
+            }
+        }
+        if !srcset.is_empty() {
+            srcset.reverse();
+            srcset.push(format!("{src} {width}w"));
+            processed.srcset = Some(srcset.join(", "));
+        }
+
+        for format in &config.formats {
+            // The `image` crate can only encode lossless WebP, which is larger than lossy sources.
+            if *format == ImageFormat::Webp && original_format != image::ImageFormat::Png {
+                continue;
+            }
+
+            let mut srcset = Vec::new();
+            let mut max_size = source.size;
+            let mut generated = false;
+            for w in all_widths.iter().rev() {
+                let variant = Variant::Modern(*format);
+                match self.variant(&mut source, build_dir, *w, &variant, max_size)? {
+                    Some((url, size)) => {
+                        srcset.push(format!("{url} {w}w"));
+                        max_size = size;
+                        generated = true;
+                    }
+                    // Browsers that pick this `<source>` still need the full width,
+                    // and the original is smaller than this format's.
+                    None if *w == width => srcset.push(format!("{src} {width}w")),
+                    None => {}
+                }
+            }
+            if generated {
+                srcset.reverse();
+                processed
+                    .sources
+                    .push((format.mime_type(), srcset.join(", ")));
+            }
+        }
+
+        // AVIF is the most efficient, so browsers should consider it first.
+        processed
+            .sources
+            .sort_by_key(|(mime, _)| *mime != "image/avif");
+        processed.sizes = Some(config.sizes.clone());
+
+        Ok(Some(processed))
+    }
+
+    /// Generate (or fetch from the cache) a single variant, copy it
+    /// next to the page in the build directory and return its URL.
+    ///
+    /// Returns `None` if the variant isn't smaller than `max_size` bytes,
+    /// or its URL and size otherwise.
+    fn variant(
+        &self,
+        source: &mut SourceImage,
+        build_dir: &Path,
+        width: u32,
+        variant: &Variant,
+        max_size: u64,
+    ) -> Result<Option<(String, u64)>, EngineError> {
+        let config = self.config.as_ref().expect("the pipeline is enabled");
+        let extension = variant.extension();
+
+        // The key covers the encoder settings, so changing them regenerates variants.
+        let key =
+            content_hash(format!("{}-{width}-{}", source.hash, variant.settings()).as_bytes());
+        let cache_path =
+            PathBuf::from(&config.cache_path).join(format!("{}.{extension}", &key[..16]));
+        if cache_path.exists() {
+            debug!("Using cached image variant {}", cache_path.display());
+        } else {
+            let image = match &mut source.decoded {
+                Some(image) => image,
+                None => source
+                    .decoded
+                    .insert(ImageReader::open(source.path)?.decode()?),
+            };
+            let resized = if width < image.width() {
+                image.resize(width, u32::MAX, FilterType::Lanczos3)
+            } else {
+                image.clone()
+            };
+            variant.encode(&resized, &cache_path)?;
+            info!("Generated image variant {}", cache_path.display());
+        }
+
+        let size = fs::metadata(&cache_path)?.len();
+        if size >= max_size {
+            debug!(
+                "Skipping image variant {} of {}, as it isn't smaller ({size} >= {max_size} bytes)",
+                cache_path.display(),
+                source.src
+            );
+            return Ok(None);
+        }
+
+        // Variants live next to the original, e.g. `img/ecc.png` -> `img/ecc.png-480.webp`,
+        // so sources that only differ in extension don't overwrite each other's.
+        let src_path = Path::new(source.src);
+        let name = src_path
+            .file_name()
+            .and_then(|s| s.to_str())
+            .ok_or_else(|| EngineError::InvalidPath(source.src.to_string()))?;
+        let file_name = format!("{name}-{width}.{extension}");
+        let url_path = match src_path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.join(&file_name),
+            _ => PathBuf::from(&file_name),
+        };
+
+        let build_path = build_dir.join(&url_path);
+        if let Some(parent) = build_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::copy(&cache_path, &build_path)?;
+
+        Ok(Some((url_path.display().to_string(), size)))
+    }
+}
+
+impl Variant {
+    fn extension(&self) -> &'static str {
+        match self {
+            Variant::Original(format) => format.extensions_str()[0],
+            Variant::Modern(ImageFormat::Webp) => "webp",
+            Variant::Modern(ImageFormat::Avif) => "avif",
+        }
+    }
+
+    /// The encoder settings, which are part of a variant's cache key.
+    fn settings(&self) -> String {
+        match self {
+            Variant::Original(image::ImageFormat::Png) => "png-best-adaptive".to_string(),
+            Variant::Original(format) => format.extensions_str()[0].to_string(),
+            Variant::Modern(ImageFormat::Webp) => "webp-lossless".to_string(),
+            Variant::Modern(ImageFormat::Avif) => {
+                format!("avif-q{AVIF_QUALITY}-s{AVIF_SPEED}")
+            }
+        }
+    }
+
+    fn encode(&self, image: &DynamicImage, path: &Path) -> Result<(), EngineError> {
+        match self {
+            // The default PNG compression is fast, and larger than most originals.
+            Variant::Original(image::ImageFormat::Png) => {
+                let writer = BufWriter::new(fs::File::create(path)?);
+                image.write_with_encoder(PngEncoder::new_with_quality(
+                    writer,
+                    CompressionType::Best,
+                    PngFilterType::Adaptive,
+                ))?
+            }
+            Variant::Original(format) => image.save_with_format(path, *format)?,
+            Variant::Modern(format) => {
+                let writer = BufWriter::new(fs::File::create(path)?);
+                let image = DynamicImage::ImageRgba8(image.to_rgba8());
+                match format {
+                    ImageFormat::Webp => {
+                        image.write_with_encoder(WebPEncoder::new_lossless(writer))?
+                    }
+                    ImageFormat::Avif => image.write_with_encoder(
+                        AvifEncoder::new_with_speed_quality(writer, AVIF_SPEED, AVIF_QUALITY),
+                    )?,
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl ImageFormat {
+    fn mime_type(&self) -> &'static str {
+        match self {
+            ImageFormat::Webp => "image/webp",
+            ImageFormat::Avif => "image/avif",
+        }
+    }
+}
+
+impl ProcessedImage {
+    /// Render the image as an `<img>`, wrapped in a `<picture>` if there are modern formats.
+    ///
+    /// `sizes` overrides the configured `sizes`, for layouts other than the main column.
+    pub(crate) fn to_html(&self, alt: &str, sizes: Option<&str>) -> String {
+        let sizes = self
+            .sizes
+            .as_ref()
+            .map(|default| {
+                format!(
+                    " sizes=\"{}\"",
+                    html_escape::encode_double_quoted_attribute(sizes.unwrap_or(default))
+                )
+            })
+            .unwrap_or_default();
+        let srcset = self
+            .srcset
+            .as_ref()
+            .map(|srcset| {
+                format!(
+                    " srcset=\"{}\"{sizes}",
+                    html_escape::encode_double_quoted_attribute(srcset)
+                )
+            })
+            .unwrap_or_default();
+        let alt = if alt.is_empty() {
+            String::new()
+        } else {
+            format!(
+                " alt=\"{}\"",
+                html_escape::encode_double_quoted_attribute(alt)
+            )
+        };
+
+        let img = format!(
+            "<img src=\"{}\"{srcset} width=\"{}\" height=\"{}\"{alt} loading=\"lazy\" decoding=\"async\">",
+            html_escape::encode_double_quoted_attribute(&self.src),
+            self.width,
+            self.height
+        );
+        if self.sources.is_empty() {
+            return img;
+        }
+
+        let sources = self
+            .sources
+            .iter()
+            .map(|(mime, srcset)| {
+                format!(
+                    "<source type=\"{mime}\" srcset=\"{}\"{sizes}>\n",
+                    html_escape::encode_double_quoted_attribute(srcset)
+                )
+            })
+            .collect::<String>();
+
+        format!("<picture>\n{sources}{img}\n</picture>")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{RgbImage, codecs::jpeg::JpegEncoder};
+
+    use super::*;
+
+    #[test]
+    fn test_skip_larger_variants() {
+        let dir = std::env::temp_dir().join(format!("images-test-{}", std::process::id()));
+        let source_dir = dir.join("content");
+        let build_dir = dir.join("build");
+        fs::create_dir_all(&source_dir).unwrap();
+
+        // Noise at a low quality: re-encoding it at a slightly smaller width is only larger.
+        let mut seed = 1u32;
+        let noise = RgbImage::from_fn(64, 64, |_, _| {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            image::Rgb([(seed >> 16) as u8, (seed >> 8) as u8, (seed >> 24) as u8])
+        });
+        let writer = fs::File::create(source_dir.join("noise.jpg")).unwrap();
+        DynamicImage::ImageRgb8(noise)
+            .write_with_encoder(JpegEncoder::new_with_quality(writer, 5))
+            .unwrap();
+
+        let pipeline = ImagePipeline::new(Some(ImageConfig {
+            widths: vec![63],
+            formats: vec![ImageFormat::Webp],
+            sizes: "100vw".to_string(),
+            cache_path: dir.join("cache").display().to_string(),
+        }))
+        .unwrap();
+        let processed = pipeline
+            .process("noise.jpg", &source_dir, &build_dir)
+            .unwrap()
+            .unwrap();
+
+        assert!(processed.srcset.is_none());
+        assert!(processed.sources.is_empty());
+        assert!(!build_dir.join("noise.jpg-63.jpg").exists());
+        assert_eq!(
+            processed.to_html("", None),
+            "<img src=\"noise.jpg\" width=\"64\" height=\"64\" loading=\"lazy\" decoding=\"async\">"
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_to_html_escapes_attributes() {
+        let image = ProcessedImage {
+            src: "a \"b\".png".to_string(),
+            width: 800,
+            height: 600,
+            srcset: Some("a \"b\".png-480.png 480w, a \"b\".png 800w".to_string()),
+            sources: vec![("image/avif", "a \"b\".png-480.avif 480w".to_string())],
+            sizes: Some("100vw".to_string()),
+        };
+
+        let html = image.to_html("a \"quoted\" word", None);
+        assert!(html.contains("srcset=\"a &quot;b&quot;.png-480.avif 480w\""));
+        assert!(html.contains("<img src=\"a &quot;b&quot;.png\" srcset=\"a &quot;b&quot;.png-480.png 480w, a &quot;b&quot;.png 800w\""));
+        assert!(html.contains("alt=\"a &quot;quoted&quot; word\""));
+    }
+}
@@ -1,30 +1,53 @@
-use std::{collections::HashMap, fs, path::Path, process};
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    ops::Range,
+    path::{Path, PathBuf},
+    process,
+};
 
 use chrono::NaiveDate;
 use log::{debug, error, info, warn};
-use pulldown_cmark::{Event, Options, Parser, Tag, TagEnd, html};
-use pulldown_cmark_toc::{GitHubSlugifier, Slugify, TableOfContents};
+use pulldown_cmark::{Event, HeadingLevel, Options, Parser, Tag, TagEnd, html};
 use regex::Regex;
 use serde::{Deserialize, Serialize};
 use syntect::{highlighting::ThemeSet, html::highlighted_html_for_string, parsing::SyntaxSet};
 use tera::{Context, Tera};
 
 use crate::engine::{
-    config::SiteConfig,
+    config::{NoteStyle, SiteConfig},
+    css::CssBundles,
     error::EngineError,
-    util::{inject_heading_ids_into_html, strip_leading_whitespace_from_html},
+    html::write_html,
+    images::{ImagePipeline, ProcessedImage},
+    quotes::QuoteBook,
+    seo::{PageSeo, build_seo, is_blog_post},
+    shortcodes::process_shortcodes,
+    social::SocialPreviews,
+    stats::{PageStats, compute_stats},
+    util::{generate_unique_id, strip_leading_whitespace_from_html},
 };
 
+/// The `sizes` of images in full-width figures.
+const FULLWIDTH_SIZES: &str = "90vw";
+/// The `sizes` of images in margin figures.
+const MARGIN_SIZES: &str = "(max-width: 760px) 90vw, 25vw";
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct TocEntry {
     pub level: u8,
+    /// The heading's text, without any markup.
     pub title: String,
+    /// The heading's inline Markdown (code, emphasis) rendered as HTML, without links.
+    pub title_html: String,
     pub id: String,
+    /// Headings nested under this one.
+    pub children: Vec<TocEntry>,
 }
 
 /// The frontmatter is parsed from markdwown
 /// files and deserialized into [`PageMetadata`].
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub(crate) struct PageMetadata {
     /// The template to be used for this page.
     pub(crate) template: Option<String>,
@@ -41,12 +64,40 @@ pub(crate) struct PageMetadata {
     /// A blog post's tags. They are arbitrary,
     /// there's no allowed tags list.
     pub(crate) tags: Option<Vec<String>>,
-    /// Table of Content entries.
+    /// Table of Content entries, nested by heading level.
     pub(crate) toc: Option<Vec<TocEntry>>,
+    /// The shallowest heading level in the ToC, overriding the site's default.
+    pub(crate) toc_min_depth: Option<u8>,
+    /// The deepest heading level in the ToC, overriding the site's default.
+    pub(crate) toc_max_depth: Option<u8>,
     /// The path for the page.
     pub(crate) path: Option<String>,
     /// Whether the page is still a draft.
     pub(crate) draft: Option<bool>,
+    /// Old paths that should redirect to this page.
+    pub(crate) aliases: Option<Vec<String>>,
+    /// How to render sidenotes and marginnotes, overriding the site's default.
+    pub(crate) notes: Option<NoteStyle>,
+    /// Content statistics, derived from the page's Markdown.
+    pub(crate) stats: Option<PageStats>,
+    /// An HTML excerpt of the page, see [`extract_summary`].
+    pub(crate) summary: Option<String>,
+    /// The image shown in social media previews. Defaults to the page's first image.
+    pub(crate) image: Option<String>,
+    /// Canonical URL and social media metadata, see [`build_seo`].
+    pub(crate) seo: Option<PageSeo>,
+    /// The id of a quote to always show in the footer.
+    pub(crate) quote: Option<String>,
+    /// Whether the page lists blog posts rather than being one, if it's under `/blog/`.
+    pub(crate) listing: Option<bool>,
+    /// Whether the page is in the search index, overriding whether its section is.
+    pub(crate) search: Option<bool>,
+    /// Whether the page's Markdown is rendered as a `Tera` template,
+    /// see [`process_tera_selectively`]. Off by default.
+    pub(crate) templating: Option<bool>,
+    /// The page's Markdown file, set when indexing pages.
+    #[serde(skip)]
+    pub(crate) source: Option<PathBuf>,
 }
 
 pub(crate) struct Highlighter {
@@ -55,18 +106,19 @@ pub(crate) struct Highlighter {
 }
 
 impl Highlighter {
-    fn new() -> Self {
+    fn new(theme_path: &str) -> Result<Self, EngineError> {
         // Load the Gruvbox theme into the theme set.
         let mut theme_set = ThemeSet::new();
-        let gruvbox = ThemeSet::get_theme("src/themes/gruvbox-dark.tmTheme").unwrap();
+        let gruvbox = ThemeSet::get_theme(theme_path)
+            .map_err(|e| EngineError::InvalidPath(format!("{theme_path}: {e}")))?;
         theme_set.themes.insert("gruvbox_dark".to_string(), gruvbox);
 
         info!("Loaded Gruvbox Dark theme");
 
-        Self {
+        Ok(Self {
             syntax_set: SyntaxSet::load_defaults_newlines(),
             theme_set,
-        }
+        })
     }
 
     fn highlight(&self, code: &str, lang: &str) -> String {
@@ -96,6 +148,14 @@ impl Highlighter {
     }
 }
 
+/// Build-wide resources shared by every page.
+pub(crate) struct PageResources<'a> {
+    pub(crate) images: &'a ImagePipeline,
+    pub(crate) previews: &'a SocialPreviews,
+    pub(crate) quotes: &'a QuoteBook,
+    pub(crate) css: &'a CssBundles,
+}
+
 /// End-to-end processing of a Markdown file.
 ///
 /// Reads the file, parses `Latex` expressions and renders them into HTML with `katex`,
@@ -104,6 +164,7 @@ pub(crate) fn process_md_file(
     tera: &mut Tera,
     tera_ctx: &mut Context,
     site_config: &SiteConfig,
+    resources: &PageResources,
     file_path: &Path,
     content_dir: &str,
     build_dir: &str,
@@ -121,16 +182,114 @@ pub(crate) fn process_md_file(
     let content = fs::read_to_string(file_path)?;
     info!("Read file {}", file_path.display());
 
+    // Images are resolved relative to the page, and their variants written next to it.
+    let source_dir = file_path.parent().unwrap_or(Path::new(content_dir));
+    let page_build_dir = build_path.parent().unwrap_or(Path::new(build_dir));
+    let process_image = |src: &str| match resources.images.process(src, source_dir, page_build_dir)
+    {
+        Ok(image) => image,
+        Err(e) => {
+            error!("Failed to process image {src}: {e}");
+            None
+        }
+    };
+
     // Split the Frontmatter from the Markdown and process the Markdown.
-    let (mut metadata, html_content) = process_md_content(&content, tera, tera_ctx)?;
+    let (mut metadata, html_content) =
+        process_md_content(&content, tera, tera_ctx, site_config, &process_image).map_err(|e| {
+            match e {
+                EngineError::Shortcode(e) => {
+                    EngineError::Shortcode(format!("{}: {e}", file_path.display()))
+                }
+                e => e,
+            }
+        })?;
     let page_path = format!("/{}", relative_path.with_extension("html").display());
+    // Blog posts without a hand-picked image get a rendered social preview.
+    if metadata.image.is_none() && is_blog_post(&metadata, &page_path) {
+        metadata.image = resources.previews.render(tera, &metadata, page_build_dir)?;
+    }
     metadata.path = Some(page_path);
+    metadata.seo = Some(build_seo(site_config, &metadata, &content)?);
+
+    render_page(
+        tera,
+        tera_ctx,
+        site_config,
+        resources,
+        &metadata,
+        &html_content,
+        &build_path,
+    )?;
+    info!(
+        "Processed {} into {}",
+        file_path.display(),
+        build_path.display()
+    );
+
+    Ok(metadata)
+}
+
+/// Render a page without a Markdown source, like `/quotes/`, whose content
+/// comes from `content_template` rendered with `tera_ctx`.
+pub(crate) fn render_virtual_page(
+    tera: &Tera,
+    tera_ctx: &Context,
+    site_config: &SiteConfig,
+    resources: &PageResources,
+    mut metadata: PageMetadata,
+    content_template: &str,
+    build_dir: &str,
+) -> Result<PageMetadata, EngineError> {
+    let path = metadata
+        .path
+        .clone()
+        .ok_or_else(|| EngineError::InvalidPath(content_template.to_string()))?;
+    let build_path = Path::new(build_dir).join(path.trim_start_matches('/'));
+
+    metadata.seo = Some(build_seo(site_config, &metadata, "")?);
+    let mut content_ctx = tera_ctx.clone();
+    content_ctx.insert("page", &metadata);
+    let html_content = tera.render(content_template, &content_ctx)?;
+
+    render_page(
+        tera,
+        tera_ctx,
+        site_config,
+        resources,
+        &metadata,
+        &html_content,
+        &build_path,
+    )?;
+    info!("Generated {} into {}", path, build_path.display());
+
+    Ok(metadata)
+}
+
+/// Render a page's HTML content with its template and write it to `build_path`.
+fn render_page(
+    tera: &Tera,
+    tera_ctx: &Context,
+    site_config: &SiteConfig,
+    resources: &PageResources,
+    metadata: &PageMetadata,
+    html_content: &str,
+    build_path: &Path,
+) -> Result<(), EngineError> {
+    let page_path = metadata.path.as_deref().unwrap_or("/");
+    let quote = resources
+        .quotes
+        .select(page_path, metadata.quote.as_deref())?;
 
     // Create a `Tera` context for the page that inherits the global context.
     let mut page_ctx = tera_ctx.clone();
     page_ctx.insert("site", &site_config);
     page_ctx.insert("page", &metadata);
     page_ctx.insert("content", &html_content);
+    page_ctx.insert("quote", &quote.to_json());
+    // Pages without math get a stylesheet without the math fonts.
+    let math = html_content.contains("class=\"katex");
+    page_ctx.insert("stylesheet", resources.css.stylesheet(math));
 
     // Select the template defined in the Frontmatter or default to "base.html".
     let template = match metadata.clone().template {
@@ -147,20 +306,10 @@ pub(crate) fn process_md_file(
     // Render the `Tera` context with the selected template.
     let rendered = tera.render(&template, &page_ctx)?;
 
-    // Create the build directory, if absent.
-    if let Some(parent) = build_path.parent() {
-        fs::create_dir_all(parent)?;
-    }
-
     // Write the rendered HTML to the build directory.
-    fs::write(&build_path, rendered)?;
-    info!(
-        "Processed {} into {}",
-        file_path.display(),
-        build_path.display()
-    );
+    write_html(site_config, build_path, &rendered)?;
 
-    Ok(metadata)
+    Ok(())
 }
 
 /// Processing of the markdown contents (split from `process_md_file` in order for this to be a pure function).
@@ -168,6 +317,8 @@ fn process_md_content(
     content: &str,
     tera: &mut Tera,
     tera_ctx: &Context,
+    site_config: &SiteConfig,
+    process_image: &dyn Fn(&str) -> Option<ProcessedImage>,
 ) -> Result<(PageMetadata, String), EngineError> {
     debug!("Processing Markdown content");
 
@@ -189,61 +340,249 @@ fn process_md_content(
         metadata.tags = Some(tags_sorted);
     }
 
-    // Extract the ToC from the unprocessed markdown.
-    let toc_generator = TableOfContents::new(&markdown);
-    let mut slugifier = GitHubSlugifier::default();
-    let toc_entries: Vec<TocEntry> = toc_generator
-        .headings()
-        .map(|heading| TocEntry {
-            level: heading.level() as u8,
-            title: heading.text(),
-            id: slugifier.slugify(&heading.text()).into_owned(),
-        })
-        .collect();
-
-    // Add the TOC to frontmatter iff there are headings.
-    metadata.toc = if toc_entries.is_empty() {
+    // Compute content statistics and the summary from the unprocessed markdown.
+    metadata.stats = Some(compute_stats(&markdown)?);
+    metadata.summary = extract_summary(&markdown, site_config.summary_paragraphs)?;
+
+    // Extract the ToC from the unprocessed markdown. The same IDs are assigned to the headings.
+    let toc_entries = collect_headings(&markdown);
+
+    // Add the TOC to frontmatter iff there are headings within the configured depth.
+    let min_depth = metadata.toc_min_depth.unwrap_or(site_config.toc.min_depth);
+    let max_depth = metadata.toc_max_depth.unwrap_or(site_config.toc.max_depth);
+    let toc_tree = build_toc_tree(
+        toc_entries
+            .iter()
+            .filter(|entry| entry.level >= min_depth && entry.level <= max_depth)
+            .cloned(),
+    );
+    metadata.toc = if toc_tree.is_empty() {
         None
     } else {
-        Some(toc_entries.clone())
+        Some(toc_tree)
     };
 
-    // Process `Tera` directives selectively (protecting code blocks),
-    // and create a temporary context with ToC data for template processing.
+    // Create a temporary context with ToC data for template processing.
     let mut temp_ctx = tera_ctx.clone();
     temp_ctx.insert("page", &metadata);
-    let markdown = process_tera_selectively(&markdown, tera, &temp_ctx)?;
+
+    // Resolve shortcodes, pointing errors to lines in the file (after the frontmatter).
+    let line_offset = content[..content.find(&markdown).unwrap_or(0)]
+        .matches('\n')
+        .count();
+    let templating = metadata.templating.unwrap_or(false);
+    let markdown = process_shortcodes(&markdown, tera, &temp_ctx, line_offset, templating)?;
+
+    // Process `Tera` directives in the prose, if the page opted in.
+    let markdown = if templating {
+        process_tera_selectively(&markdown, tera, &temp_ctx)?
+    } else {
+        markdown
+    };
 
     // Convert plain HTML `<img>` into TufteCSS image format (images on sidenotes and marginnotes are ignored).
-    let markdown_with_figures = convert_img_md_to_tufte(&markdown);
+    let markdown_with_figures = convert_img_md_to_tufte(&markdown, process_image);
 
     // Strip leading whitespace from HTML blocks (thx for that, CommonMark).
     let markdown_stripped = strip_leading_whitespace_from_html(&markdown_with_figures);
 
+    // Process epigraphs, full-width sections and new thoughts into TufteCSS classes.
+    let markdown_blocks = process_tufte_blocks(&markdown_stripped)?;
+
     // Process and convert sidenote notation into TufteCSS classes.
-    let markdown_sidenotes = process_tufte_notes(&markdown_stripped)?;
+    let note_style = metadata.notes.unwrap_or(site_config.notes);
+    let markdown_sidenotes = process_tufte_notes(&markdown_blocks, note_style)?;
 
     // Process `LaTeX` expressions with `katex-rs`.
     let markdown_katex = process_katex(&markdown_sidenotes)?;
 
-    // Process code blocks with `syntect`.
-    let markdown_syntect = process_syntect(&markdown_katex)?;
+    // Process code blocks with `syntect`, and assign heading IDs.
+    let markdown_syntect =
+        process_syntect(&markdown_katex, &toc_entries, &site_config.highlight_theme)?;
 
     // Finally, process the rest of the markdown into HTML.
-    let parser = Parser::new_ext(
-        &markdown_syntect,
-        Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH,
-    );
+    let parser = Parser::new_ext(&markdown_syntect, MD_OPTIONS);
     let mut html_content = String::new();
     html::push_html(&mut html_content, parser);
 
-    // Inject heading IDs into the final HTML string.
-    let html_with_ids = inject_heading_ids_into_html(&html_content)?;
+    Ok((metadata, html_content))
+}
+
+/// The marker separating a page's summary from the rest of its content.
+const SUMMARY_MARKER: &str = "<!-- more -->";
+
+/// Extract a page's summary as HTML: every paragraph before a `<!-- more -->` marker or,
+/// if there's no marker, the first `paragraphs` paragraphs. Headings, images, epigraphs,
+/// `:::` fences, sidenotes, marginnotes and `Tera` directives are stripped from the summary.
+///
+/// Returns `None` if there's no marker and `paragraphs` is `None`.
+pub(crate) fn extract_summary(
+    markdown: &str,
+    paragraphs: Option<usize>,
+) -> Result<Option<String>, EngineError> {
+    let (markdown, limit) = match (markdown.find(SUMMARY_MARKER), paragraphs) {
+        (Some(marker), _) => (&markdown[..marker], usize::MAX),
+        (None, Some(paragraphs)) => (markdown, paragraphs),
+        (None, None) => return Ok(None),
+    };
+
+    // Strip everything that doesn't belong in an excerpt.
+    let note_def_rgx = Regex::new(r"(?m)^\[[\^*][^\]]+\]:\s*.*(?:\n(?:[ \t]*\n)*[ \t]+.*)*")?;
+    let note_ref_rgx = Regex::new(r"\[[\^*][^\]]+\]")?;
+    let img_rgx = Regex::new(r"!\[[^\]]*\]\([^)]*\)(?:\{[^}]*\})?")?;
+    let tera_rgx = Regex::new(r"(?s)\{%.*?%\}|\{\{.*?\}\}")?;
+    let newthought_rgx = Regex::new(r":newthought\[([^\]]+)\]")?;
+    // Epigraphs quote someone else, so they go with their attributions.
+    let epigraph_rgx = Regex::new(r"(?ms)^:::epigraph[ \t]*$.*?^:::[ \t]*$")?;
+    let fence_rgx = Regex::new(r"(?m)^:::[a-z]*[ \t]*$")?;
+    let cleaned = epigraph_rgx.replace_all(markdown, "");
+    let cleaned = fence_rgx.replace_all(&cleaned, "");
+    let cleaned = note_def_rgx.replace_all(&cleaned, "");
+    let cleaned = note_ref_rgx.replace_all(&cleaned, "");
+    let cleaned = img_rgx.replace_all(&cleaned, "");
+    let cleaned = tera_rgx.replace_all(&cleaned, "");
+    let cleaned = newthought_rgx.replace_all(&cleaned, "$1");
+
+    // Keep only top-level paragraphs with some text left in them.
+    let mut summary_paragraphs: Vec<&str> = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (event, range) in Parser::new_ext(&cleaned, MD_OPTIONS).into_offset_iter() {
+        match event {
+            Event::Start(Tag::Paragraph) if depth == 0 => start = range.start,
+            Event::End(TagEnd::Paragraph) if depth == 0 => {
+                let paragraph = cleaned[start..range.end].trim();
+                if !paragraph.is_empty() && summary_paragraphs.len() < limit {
+                    summary_paragraphs.push(paragraph);
+                }
+            }
+            Event::Start(tag) if is_container(&tag) => depth += 1,
+            Event::End(tag) if is_container_end(&tag) => depth -= 1,
+            _ => {}
+        }
+    }
+
+    if summary_paragraphs.is_empty() {
+        return Ok(None);
+    }
+
+    let summary_md = process_katex(&summary_paragraphs.join("\n\n"))?;
+    let mut summary = String::new();
+    html::push_html(&mut summary, Parser::new_ext(&summary_md, MD_OPTIONS));
+
+    Ok(Some(summary.trim().to_string()))
+}
+
+/// Whether a tag can contain paragraphs that aren't top-level.
+fn is_container(tag: &Tag) -> bool {
+    matches!(
+        tag,
+        Tag::BlockQuote(_) | Tag::List(_) | Tag::Item | Tag::FootnoteDefinition(_)
+    )
+}
 
-    Ok((metadata, html_with_ids))
+/// Whether a tag closes a container, see [`is_container`].
+fn is_container_end(tag: &TagEnd) -> bool {
+    matches!(
+        tag,
+        TagEnd::BlockQuote(_) | TagEnd::List(_) | TagEnd::Item | TagEnd::FootnoteDefinition
+    )
 }
 
-/// Process only actual `Tera` directives, leaving other content untouched.
+/// The Markdown extensions enabled when parsing pages.
+pub(crate) const MD_OPTIONS: Options = Options::ENABLE_TABLES
+    .union(Options::ENABLE_STRIKETHROUGH)
+    .union(Options::ENABLE_HEADING_ATTRIBUTES);
+
+/// Collect every heading in the Markdown, assigning each a unique ID.
+///
+/// The ID is either set with `{#id}` after the heading's text, or a slug of the heading's
+/// text, including the contents of inline code, links and emphasis. Custom IDs are reserved
+/// first, so generated IDs never collide with them.
+pub(crate) fn collect_headings(markdown: &str) -> Vec<TocEntry> {
+    struct Heading<'a> {
+        level: HeadingLevel,
+        title: String,
+        id: Option<String>,
+        events: Vec<Event<'a>>,
+    }
+
+    let mut headings: Vec<Heading> = Vec::new();
+    let mut current: Option<Heading> = None;
+
+    for event in Parser::new_ext(markdown, MD_OPTIONS) {
+        match event {
+            Event::Start(Tag::Heading { level, id, .. }) => {
+                current = Some(Heading {
+                    level,
+                    title: String::new(),
+                    id: id.map(|id| id.to_string()),
+                    events: Vec::new(),
+                });
+            }
+            Event::End(TagEnd::Heading(_)) => {
+                if let Some(heading) = current.take() {
+                    headings.push(heading);
+                }
+            }
+            // Links are dropped, since ToC entries are links themselves.
+            Event::Start(Tag::Link { .. }) | Event::End(TagEnd::Link) => {}
+            event => {
+                if let Some(heading) = current.as_mut() {
+                    if let Event::Text(text) | Event::Code(text) = &event {
+                        heading.title.push_str(text);
+                    }
+                    heading.events.push(event);
+                }
+            }
+        }
+    }
+
+    let mut used_ids: HashSet<String> = headings.iter().filter_map(|h| h.id.clone()).collect();
+
+    headings
+        .into_iter()
+        .map(|heading| {
+            let title = heading.title.trim().to_string();
+            let id = heading
+                .id
+                .unwrap_or_else(|| generate_unique_id(&title, &mut used_ids));
+            let mut title_html = String::new();
+            html::push_html(&mut title_html, heading.events.into_iter());
+
+            TocEntry {
+                level: heading.level as u8,
+                title,
+                title_html: title_html.trim().to_string(),
+                id,
+                children: Vec::new(),
+            }
+        })
+        .collect()
+}
+
+/// Nest a flat list of ToC entries under their closest preceding shallower heading.
+fn build_toc_tree(entries: impl Iterator<Item = TocEntry>) -> Vec<TocEntry> {
+    fn insert(siblings: &mut Vec<TocEntry>, entry: TocEntry) {
+        match siblings.last_mut() {
+            Some(last) if last.level < entry.level => insert(&mut last.children, entry),
+            _ => siblings.push(entry),
+        }
+    }
+
+    let mut tree = Vec::new();
+    for entry in entries {
+        insert(&mut tree, entry);
+    }
+
+    tree
+}
+
+/// Render a page's Markdown as a `Tera` template, but only its prose.
+///
+/// Code, HTML and heading attributes (`{#id}`, which would open a `Tera` comment)
+/// are wrapped in `{% raw %}`, so they're left untouched. Authors can use `{% raw %}`
+/// anywhere in the prose to do the same.
 fn process_tera_selectively(
     content: &str,
     tera: &mut Tera,
@@ -256,50 +595,66 @@ fn process_tera_selectively(
         return Ok(content.to_string());
     }
 
-    // Find and temporarily replace code blocks to protect them.
-    let mut protected_blocks = Vec::new();
-    let mut counter = 0;
-
-    // Protect fenced code blocks.
-    let code_block_rgx = Regex::new(r"(?s)```[^\n]*\n.*?```")?;
-    let mut protected_content = code_block_rgx
-        .replace_all(content, |caps: &regex::Captures| {
-            let placeholder = format!("__PROTECTED_BLOCK_{counter}__");
-            protected_blocks.push(caps[0].to_string());
-            counter += 1;
-            placeholder
-        })
-        .to_string();
-
-    // Protect inline code.
-    let code_inline_rgx = Regex::new(r"`[^`]+`")?;
-    protected_content = code_inline_rgx
-        .replace_all(&protected_content, |caps: &regex::Captures| {
-            let placeholder = format!("__PROTECTED_BLOCK_{counter}__");
-            protected_blocks.push(caps[0].to_string());
-            counter += 1;
-            placeholder
-        })
-        .to_string();
+    let raw_start_rgx = Regex::new(r"\{%-?\s*raw\s*-?%\}")?;
+    let raw_end_rgx = Regex::new(r"\{%-?\s*endraw\s*-?%\}")?;
+    let heading_attrs_rgx = Regex::new(r"\{#[^}]*\}")?;
 
-    // Process with content with `Tera`.
-    let processed = tera.render_str(&protected_content, tera_ctx)?;
+    // Find the source ranges of everything that isn't prose.
+    let mut literals: Vec<Range<usize>> = Vec::new();
+    for (event, range) in Parser::new_ext(content, MD_OPTIONS).into_offset_iter() {
+        match event {
+            Event::Start(Tag::CodeBlock(_) | Tag::HtmlBlock)
+            | Event::Code(_)
+            | Event::Html(_)
+            | Event::InlineHtml(_) => literals.push(range),
+            Event::Start(Tag::Heading { .. }) => literals.extend(
+                heading_attrs_rgx
+                    .find_iter(&content[range.clone()])
+                    .map(|m| range.start + m.start()..range.start + m.end()),
+            ),
+            _ => {}
+        }
+    }
+    literals.sort_by_key(|range| range.start);
+
+    let mut protected = String::with_capacity(content.len());
+    let mut cursor = 0;
+    for range in literals {
+        // The author's own `{% raw %}` blocks are kept as they are, along with anything inside them.
+        while let Some(raw_start) = raw_start_rgx
+            .find_at(content, cursor)
+            .filter(|m| m.start() < range.start)
+        {
+            let raw_end = raw_end_rgx
+                .find_at(content, raw_start.end())
+                .map_or(content.len(), |m| m.end());
+            protected.push_str(&content[cursor..raw_end]);
+            cursor = raw_end;
+        }
 
-    // Restore protected blocks.
-    let mut result = processed;
-    for (i, block) in protected_blocks.iter().enumerate() {
-        let placeholder = format!("__PROTECTED_BLOCK_{i}__");
-        result = result.replace(&placeholder, block);
+        // Ranges can nest, like HTML inside an HTML block, or be partly inside a `{% raw %}` block.
+        let start = range.start.max(cursor);
+        if start < range.end {
+            protected.push_str(&content[cursor..start]);
+            // A literal `{% endraw %}` would end the block early, so it's output as a string.
+            let literal = raw_end_rgx.replace_all(
+                &content[start..range.end],
+                "{% endraw %}{{ \"$0\" }}{% raw %}",
+            );
+            protected.push_str(&format!("{{% raw %}}{literal}{{% endraw %}}"));
+            cursor = range.end;
+        }
     }
+    protected.push_str(&content[cursor..]);
 
-    Ok(result)
+    Ok(tera.render_str(&protected, tera_ctx)?)
 }
 
 /// Process inline (`$ <expr> $`) and display (`$$ <expr> $$) LaTeX into HTML with `katex`.
 ///
 /// The KaTeX CSS file must be available. You can get it from
 /// <https://cdn.jsdelivr.net/npm/katex@0.16.22/dist/katex.css>
-fn process_katex(content: &str) -> Result<String, EngineError> {
+pub(crate) fn process_katex(content: &str) -> Result<String, EngineError> {
     debug!("Processing Katex");
 
     let mut result = content.to_string();
@@ -358,14 +713,19 @@ fn process_katex(content: &str) -> Result<String, EngineError> {
 }
 
 /// Process code blocks into HTML with `syntect`.
-fn process_syntect(content: &str) -> Result<String, EngineError> {
+///
+/// Also assigns IDs and self-link anchors to headings, from the matching ones in `headings`
+/// (see [`collect_headings`] and [`match_headings`]). Headings that weren't collected
+/// (e.g. from shortcodes or included templates) get a fresh unique ID.
+fn process_syntect(
+    content: &str,
+    headings: &[TocEntry],
+    theme_path: &str,
+) -> Result<String, EngineError> {
     debug!("Processing syntect...");
 
-    let highlighter = Highlighter::new();
-    let parser = Parser::new_ext(
-        content,
-        Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH,
-    );
+    let highlighter = Highlighter::new(theme_path)?;
+    let parser = Parser::new_ext(content, MD_OPTIONS);
     let mut html_content = String::new();
 
     let events: Vec<_> = parser.collect();
@@ -374,6 +734,11 @@ fn process_syntect(content: &str) -> Result<String, EngineError> {
     let mut code_lang: String = String::new();
     let mut code_content: String = String::new();
 
+    let mut used_ids: HashSet<String> = headings.iter().map(|h| h.id.clone()).collect();
+    let mut matched_headings = match_headings(&events, headings).into_iter();
+    let mut heading: Option<Tag> = None;
+    let mut heading_events: Vec<Event> = Vec::new();
+
     for event in events {
         match event {
             Event::Start(Tag::CodeBlock(lang)) => {
@@ -400,8 +765,54 @@ fn process_syntect(content: &str) -> Result<String, EngineError> {
             Event::Text(text) if in_code_block => {
                 code_content.push_str(&text);
             }
+            // Buffer the heading's contents until its ID is known.
+            Event::Start(tag @ Tag::Heading { .. }) => {
+                heading = Some(tag);
+                heading_events.clear();
+            }
+            Event::End(TagEnd::Heading(_)) => {
+                let Some(Tag::Heading {
+                    level,
+                    id,
+                    classes,
+                    attrs,
+                }) = heading.take()
+                else {
+                    continue;
+                };
+
+                let id = match matched_headings.next().flatten() {
+                    Some(entry) => headings[entry].id.clone(),
+                    None => match id {
+                        Some(id) => id.to_string(),
+                        None => {
+                            let title: String = heading_events
+                                .iter()
+                                .filter_map(|event| match event {
+                                    Event::Text(text) | Event::Code(text) => Some(text.as_ref()),
+                                    _ => None,
+                                })
+                                .collect();
+                            generate_unique_id(&title, &mut used_ids)
+                        }
+                    },
+                };
+
+                let anchor = format!(r##" <a class="heading-anchor" href="#{id}">¶</a>"##);
+                processed_events.push(Event::Start(Tag::Heading {
+                    level,
+                    id: Some(id.into()),
+                    classes,
+                    attrs,
+                }));
+                processed_events.append(&mut heading_events);
+                processed_events.push(Event::InlineHtml(anchor.into()));
+                processed_events.push(Event::End(TagEnd::Heading(level)));
+            }
             _ => {
-                if !in_code_block {
+                if heading.is_some() {
+                    heading_events.push(event);
+                } else if !in_code_block {
                     processed_events.push(event);
                 }
             }
@@ -412,8 +823,190 @@ fn process_syntect(content: &str) -> Result<String, EngineError> {
     Ok(html_content)
 }
 
+/// Match each heading in `events` to one of the `collected` headings, returning its index.
+///
+/// Headings are matched by level and text, in order. Those left over, like headings
+/// with math, are then paired in order with the collected headings of the same level
+/// between the same matched neighbors. Headings added after collecting are left unmatched.
+fn match_headings(events: &[Event], collected: &[TocEntry]) -> Vec<Option<usize>> {
+    let mut rendered: Vec<(u8, String)> = Vec::new();
+    let mut current: Option<(u8, String)> = None;
+    for event in events {
+        match event {
+            Event::Start(Tag::Heading { level, .. }) => {
+                current = Some((*level as u8, String::new()))
+            }
+            Event::End(TagEnd::Heading(_)) => rendered.extend(current.take()),
+            Event::Text(text) | Event::Code(text) => {
+                if let Some((_, title)) = current.as_mut() {
+                    title.push_str(text);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut matches = vec![None; rendered.len()];
+    let mut used = vec![false; collected.len()];
+    let mut cursor = 0;
+    for (i, (level, title)) in rendered.iter().enumerate() {
+        if let Some(j) = (cursor..collected.len())
+            .find(|&j| collected[j].level == *level && collected[j].title == title.trim())
+        {
+            matches[i] = Some(j);
+            used[j] = true;
+            cursor = j + 1;
+        }
+    }
+
+    for i in 0..rendered.len() {
+        if matches[i].is_some() {
+            continue;
+        }
+        let start = matches[..i]
+            .iter()
+            .rev()
+            .find_map(|j| *j)
+            .map_or(0, |j| j + 1);
+        let end = matches[i..]
+            .iter()
+            .find_map(|j| *j)
+            .unwrap_or(collected.len());
+        if let Some(j) = (start..end).find(|&j| !used[j] && collected[j].level == rendered[i].0) {
+            matches[i] = Some(j);
+            used[j] = true;
+        }
+    }
+
+    matches
+}
+
+/// Process TufteCSS block and inline extensions:
+///
+/// - `:::epigraph` containers, where a line starting with `--` attributes the quote above it.
+/// - `:::fullwidth` containers, for content spanning the whole page.
+/// - `:newthought[Opening words]`, rendered in small caps.
+///
+/// Containers are closed by a line with a lone `:::`. Fenced code and inline code are left untouched.
+/// Margin figures are handled by [`convert_img_md_to_tufte`].
+fn process_tufte_blocks(content: &str) -> Result<String, EngineError> {
+    debug!("Processing Tufte blocks...");
+
+    let newthought_rgx = Regex::new(r":newthought\[([^\]]+)\]")?;
+    let attribution_rgx = Regex::new(r"^(?:--|—)\s*(.+)$")?;
+
+    let mut result_lines: Vec<String> = Vec::new();
+    let mut in_code_block = false;
+    let mut container: Option<&str> = None;
+    let mut quote: Vec<&str> = Vec::new();
+
+    for line in content.lines() {
+        let trimmed = line.trim();
+
+        // Fenced code blocks are copied verbatim.
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            in_code_block = !in_code_block;
+            result_lines.push(line.to_string());
+            continue;
+        }
+        if in_code_block {
+            result_lines.push(line.to_string());
+            continue;
+        }
+
+        match (container, trimmed) {
+            (None, ":::epigraph") => {
+                container = Some("epigraph");
+                result_lines.push("<div class=\"epigraph\">".to_string());
+                continue;
+            }
+            (None, ":::fullwidth") => {
+                container = Some("fullwidth");
+                result_lines.push("<div class=\"fullwidth\">\n".to_string());
+                continue;
+            }
+            (Some("epigraph"), ":::") => {
+                if !quote.is_empty() {
+                    result_lines.push(render_epigraph_quote(&quote, None));
+                    quote.clear();
+                }
+                container = None;
+                result_lines.push("</div>\n".to_string());
+                continue;
+            }
+            (Some(_), ":::") => {
+                container = None;
+                result_lines.push("\n</div>\n".to_string());
+                continue;
+            }
+            (Some("epigraph"), _) => {
+                if let Some(caps) = attribution_rgx.captures(trimmed) {
+                    result_lines.push(render_epigraph_quote(&quote, Some(&caps[1])));
+                    quote.clear();
+                } else if !trimmed.is_empty() || !quote.is_empty() {
+                    quote.push(line);
+                }
+                continue;
+            }
+            _ => {}
+        }
+
+        // Inline code spans alternate with regular text when splitting on backticks.
+        let processed = line
+            .split('`')
+            .enumerate()
+            .map(|(i, segment)| {
+                if i % 2 == 0 {
+                    newthought_rgx
+                        .replace_all(segment, r#"<span class="newthought">$1</span>"#)
+                        .to_string()
+                } else {
+                    segment.to_string()
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("`");
+        result_lines.push(processed);
+    }
+
+    if let Some(container) = container {
+        warn!("Unclosed `:::{container}` container");
+    }
+
+    Ok(result_lines.join("\n"))
+}
+
+/// Render a single epigraph quote, with an optional attribution rendered as inline Markdown.
+fn render_epigraph_quote(quote: &[&str], attribution: Option<&str>) -> String {
+    let footer = attribution
+        .map(|attribution| format!("<footer>{}</footer>\n", render_inline_md(attribution)))
+        .unwrap_or_default();
+
+    format!(
+        "<blockquote>\n\n{}\n\n{footer}</blockquote>",
+        quote.join("\n").trim()
+    )
+}
+
+/// Render a snippet of Markdown into HTML without the enclosing `<p>`.
+pub(crate) fn render_inline_md(markdown: &str) -> String {
+    let parser = Parser::new_ext(markdown, Options::ENABLE_STRIKETHROUGH);
+    let mut html_content = String::new();
+    html::push_html(&mut html_content, parser);
+
+    let trimmed = html_content.trim();
+    trimmed
+        .strip_prefix("<p>")
+        .and_then(|html| html.strip_suffix("</p>"))
+        .unwrap_or(trimmed)
+        .to_string()
+}
+
 /// Process sidenote (`[^key]`) and marginnote (`[*key]`) into TufteCSS classes.
-fn process_tufte_notes(content: &str) -> Result<String, EngineError> {
+///
+/// Note bodies are rendered as Markdown, including KaTeX and inline code. With
+/// [`NoteStyle::Footnotes`], all notes are rendered as numbered footnotes at the end of the page.
+fn process_tufte_notes(content: &str, style: NoteStyle) -> Result<String, EngineError> {
     debug!("Processing Tufte notes...");
 
     let mut result_lines = Vec::new();
@@ -432,34 +1025,20 @@ fn process_tufte_notes(content: &str) -> Result<String, EngineError> {
         // Match on sidenote notation: `[^key]`
         if let Some(caps) = sidenote_rgx.captures(line) {
             let key = caps[1].to_string();
-            let mut content = caps[2].to_string();
+            let (content, next) = collect_note_body(&caps[2], &lines, i + 1);
+            i = next;
 
-            // Sidenote notations can be multiline, given they are indented with 4 spaces.
-            i += 1;
-            while i < lines.len() && (lines[i].starts_with("    ") || lines[i].starts_with("\t")) {
-                content.push(' ');
-                content.push_str(lines[i].trim());
-                i += 1;
-            }
-
-            sidenotes.insert(key, content.trim().to_string());
+            sidenotes.insert(key, content);
             continue;
         }
 
         // Match on marginnote definition: `[*key]`
         if let Some(caps) = marginnote_rgx.captures(line) {
             let key = caps[1].to_string();
-            let mut content = caps[2].to_string();
+            let (content, next) = collect_note_body(&caps[2], &lines, i + 1);
+            i = next;
 
-            // Marginnote notations can be multiline, given they are indented with 4 spaces.
-            i += 1;
-            while i < lines.len() && (lines[i].starts_with("    ") || lines[i].starts_with("\t")) {
-                content.push(' ');
-                content.push_str(lines[i].trim());
-                i += 1;
-            }
-
-            marginnotes.insert(key, content.trim().to_string());
+            marginnotes.insert(key, content);
             continue;
         }
 
@@ -467,16 +1046,46 @@ fn process_tufte_notes(content: &str) -> Result<String, EngineError> {
         i += 1;
     }
 
+    // Footnotes are collected in order of first reference, with how many times they're
+    // referenced, and appended to the page.
+    let mut footnotes: Vec<(String, String, usize)> = Vec::new();
+    // Notes referenced more than once get a suffix on every id after the first, like `sn-key-2`.
+    let mut ref_counts: HashMap<String, usize> = HashMap::new();
+
     // Replace numbered sidenote references: `[^key]`
     let mut sidenote_ctr = 0;
     let mut result = result_lines.join("\n");
+    let mut render_error = None;
     result = sidenote_ref_rgx.replace_all(&result, |caps: &regex::Captures| {
         let key = &caps[1];
         if let Some(sidenote_content) = sidenotes.get(key) {
+            let id = format!("sn-{key}");
+            if style == NoteStyle::Footnotes
+                && let Some(footnote) = footnotes.iter().position(|(fid, ..)| *fid == id)
+            {
+                footnotes[footnote].2 += 1;
+                return footnote_ref(&id, footnote + 1, footnotes[footnote].2);
+            }
+            let nth = ref_counts.entry(id.clone()).or_default();
+            *nth += 1;
+            let dom_id = suffixed_id(&id, *nth);
             sidenote_ctr += 1;
-            format!(
-                r#"<sup>{sidenote_ctr}</sup><label for="sn-{key}" class="margin-toggle sidenote-number"></label><input type="checkbox" id="sn-{key}" class="margin-toggle"/><span class="sidenote"><sup>{sidenote_ctr}</sup> {sidenote_content}</span>"#
-            )
+            let rendered = match render_note_md(sidenote_content, style) {
+                Ok(rendered) => rendered,
+                Err(e) => {
+                    render_error = Some(e);
+                    return String::new();
+                }
+            };
+            match style {
+                NoteStyle::Margin => format!(
+                    r#"<sup>{sidenote_ctr}</sup><label for="{dom_id}" class="margin-toggle sidenote-number"></label><input type="checkbox" id="{dom_id}" class="margin-toggle"/><span class="sidenote"><sup>{sidenote_ctr}</sup> {rendered}</span>"#
+                ),
+                NoteStyle::Footnotes => {
+                    footnotes.push((id.clone(), rendered, 1));
+                    footnote_ref(&id, footnotes.len(), 1)
+                }
+            }
         } else {
             format!("[^{key} 404NotFound]")
         }
@@ -486,28 +1095,331 @@ fn process_tufte_notes(content: &str) -> Result<String, EngineError> {
     result = marginnote_ref_rgx.replace_all(&result, |caps: &regex::Captures| {
         let key = &caps[1];
         if let Some(marginnote_content) = marginnotes.get(key) {
-            format!(
-                r#"<label for="mn-{key}" class="margin-toggle">⊕</label><input type="checkbox" id="mn-{key}" class="margin-toggle"/><span class="marginnote">{marginnote_content}</span>"#
-            )
+            let id = format!("mn-{key}");
+            if style == NoteStyle::Footnotes
+                && let Some(footnote) = footnotes.iter().position(|(fid, ..)| *fid == id)
+            {
+                footnotes[footnote].2 += 1;
+                return footnote_ref(&id, footnote + 1, footnotes[footnote].2);
+            }
+            let nth = ref_counts.entry(id.clone()).or_default();
+            *nth += 1;
+            let dom_id = suffixed_id(&id, *nth);
+            let rendered = match render_note_md(marginnote_content, style) {
+                Ok(rendered) => rendered,
+                Err(e) => {
+                    render_error = Some(e);
+                    return String::new();
+                }
+            };
+            match style {
+                NoteStyle::Margin => format!(
+                    r#"<label for="{dom_id}" class="margin-toggle">⊕</label><input type="checkbox" id="{dom_id}" class="margin-toggle"/><span class="marginnote">{rendered}</span>"#
+                ),
+                NoteStyle::Footnotes => {
+                    footnotes.push((id.clone(), rendered, 1));
+                    footnote_ref(&id, footnotes.len(), 1)
+                }
+            }
         } else {
             format!("[*{key} - NOT FOUND]")
         }
     }).to_string();
 
+    if let Some(e) = render_error {
+        return Err(e);
+    }
+
+    if !footnotes.is_empty() {
+        let items = footnotes
+            .iter()
+            .map(|(id, content, refs)| {
+                // One back-link per reference, numbered after the first.
+                let backrefs = (1..=*refs)
+                    .map(|nth| {
+                        let label = if nth == 1 {
+                            "↩".to_string()
+                        } else {
+                            format!("↩<sup>{nth}</sup>")
+                        };
+                        format!(
+                            r##" <a href="#fnref-{}" class="footnote-backref">{label}</a>"##,
+                            suffixed_id(id, nth)
+                        )
+                    })
+                    .collect::<String>();
+                format!(r#"<li id="fn-{id}">{content}{backrefs}</li>"#)
+            })
+            .collect::<String>();
+        result.push_str(&format!(
+            "\n\n<section class=\"footnotes\"><hr/><ol>{items}</ol></section>\n"
+        ));
+    }
+
     Ok(result)
 }
 
+/// Collect a note's body, starting with the text after its definition and followed by
+/// continuation lines, which must be indented with 4 spaces or a tab. Blank lines are
+/// allowed between indented lines, for notes with multiple paragraphs.
+///
+/// Returns the body and the index of the first line after it.
+fn collect_note_body(first_line: &str, lines: &[&str], mut i: usize) -> (String, usize) {
+    let is_indented = |line: &str| line.starts_with("    ") || line.starts_with('\t');
+
+    let mut body = vec![first_line.to_string()];
+    while i < lines.len() {
+        let line = lines[i];
+        if is_indented(line) {
+            let unindented = line
+                .strip_prefix("    ")
+                .or_else(|| line.strip_prefix('\t'))
+                .unwrap_or(line);
+            body.push(unindented.to_string());
+        } else if line.trim().is_empty() && lines.get(i + 1).is_some_and(|next| is_indented(next)) {
+            body.push(String::new());
+        } else {
+            break;
+        }
+        i += 1;
+    }
+
+    (body.join("\n").trim().to_string(), i)
+}
+
+/// The `nth` reference to a footnote, pointing to the footnote with `id`.
+fn footnote_ref(id: &str, number: usize, nth: usize) -> String {
+    format!(
+        r##"<sup class="footnote-ref"><a href="#fn-{id}" id="fnref-{}">{number}</a></sup>"##,
+        suffixed_id(id, nth)
+    )
+}
+
+/// The id of the `nth` element for a note: `id` itself for the first, then `id-2`, `id-3`...
+fn suffixed_id(id: &str, nth: usize) -> String {
+    if nth == 1 {
+        id.to_string()
+    } else {
+        format!("{id}-{nth}")
+    }
+}
+
+/// Render a note's body as Markdown, to be spliced into the page as raw HTML.
+///
+/// Math is rendered before the Markdown, so emphasis markers don't mangle expressions.
+/// Margin notes live inside a `<span>`, so block elements are degraded into inline ones.
+/// The result is escaped so later passes over the page leave it untouched.
+fn render_note_md(content: &str, style: NoteStyle) -> Result<String, EngineError> {
+    // Inline code spans alternate with regular text when splitting on backticks.
+    let with_math = content
+        .split('`')
+        .enumerate()
+        .map(|(i, segment)| {
+            if i % 2 == 0 {
+                process_katex(segment)
+            } else {
+                Ok(segment.to_string())
+            }
+        })
+        .collect::<Result<Vec<_>, _>>()?
+        .join("`");
+
+    let parser = Parser::new_ext(&with_math, MD_OPTIONS);
+    let mut html_content = String::new();
+    html::push_html(&mut html_content, parser);
+
+    let html_content = match style {
+        NoteStyle::Margin => degrade_blocks_to_inline(&html_content)?,
+        NoteStyle::Footnotes => html_content.trim().to_string(),
+    };
+
+    Ok(escape_md_in_html(&html_content))
+}
+
+/// Degrade block-level HTML into inline HTML, so it can live inside a `<span>`.
+fn degrade_blocks_to_inline(html: &str) -> Result<String, EngineError> {
+    let tag_rgx = Regex::new(
+        r"<(/?)(p|ul|ol|li|pre|h[1-6]|blockquote|table|thead|tbody|tr|th|td|hr)\b[^>]*>",
+    )?;
+
+    let mut result = String::new();
+    let mut last = 0;
+    let mut in_pre = false;
+    let mut list_counters: Vec<Option<usize>> = Vec::new();
+    for caps in tag_rgx.captures_iter(html) {
+        let tag = caps.get(0).unwrap();
+        let text = &html[last..tag.start()];
+        last = tag.end();
+
+        // Keep line breaks in code, and join every other line.
+        if in_pre {
+            result.push_str(&text.replace("\n</code>", "</code>").replace('\n', "<br/>"));
+        } else {
+            result.push_str(&text.replace('\n', " "));
+        }
+
+        let closing = !caps[1].is_empty();
+        let replacement = match (&caps[2], closing) {
+            ("p", false) | ("ul", true) | ("ol", true) | ("blockquote", false) => "",
+            ("ul", false) => {
+                list_counters.push(None);
+                ""
+            }
+            ("ol", false) => {
+                list_counters.push(Some(0));
+                ""
+            }
+            ("li", false) => {
+                let marker = match list_counters.last_mut() {
+                    Some(Some(ctr)) => {
+                        *ctr += 1;
+                        format!("<br/>{ctr}. ")
+                    }
+                    _ => "<br/>• ".to_string(),
+                };
+                result.push_str(&marker);
+                ""
+            }
+            ("pre", false) => {
+                in_pre = true;
+                "<br/>"
+            }
+            ("pre", true) => {
+                in_pre = false;
+                "<br/>"
+            }
+            (h, false) if h.starts_with('h') && h != "hr" => "<br/><strong>",
+            (h, true) if h.starts_with('h') && h != "hr" => "</strong><br/>",
+            ("th", true) | ("td", true) => " ",
+            ("p", true) | ("blockquote", true) | ("tr", true) | ("hr", _) => "<br/>",
+            _ => "",
+        };
+        if caps[2] == *"ol" || caps[2] == *"ul" {
+            if closing {
+                list_counters.pop();
+            }
+            result.push_str("<br/>");
+        }
+        result.push_str(replacement);
+    }
+    result.push_str(&html[last..].replace('\n', " "));
+
+    // Collapse the line breaks left behind by adjacent blocks.
+    let br_rgx = Regex::new(r"(?:\s*<br/>\s*)+")?;
+    let result = br_rgx.replace_all(&result, "<br/>");
+
+    Ok(result
+        .trim()
+        .trim_start_matches("<br/>")
+        .trim_end_matches("<br/>")
+        .trim()
+        .to_string())
+}
+
+/// Escape characters in HTML text that later Markdown and KaTeX passes would interpret,
+/// and newlines that would break the paragraph the HTML is spliced into.
+fn escape_md_in_html(html: &str) -> String {
+    let mut result = String::with_capacity(html.len());
+    let mut in_tag = false;
+    let mut in_pre = false;
+
+    for (i, c) in html.char_indices() {
+        match c {
+            '<' => {
+                in_tag = true;
+                if html[i..].starts_with("<pre") {
+                    in_pre = true;
+                } else if html[i..].starts_with("</pre") {
+                    in_pre = false;
+                }
+                result.push(c);
+            }
+            '>' => {
+                in_tag = false;
+                result.push(c);
+            }
+            '\n' if in_pre => result.push_str("&#10;"),
+            '\n' => result.push(' '),
+            '*' | '_' | '`' | '[' | ']' | '\\' | '$' | '~' if !in_tag => {
+                result.push_str(&format!("&#{};", c as u32));
+            }
+            _ => result.push(c),
+        }
+    }
+
+    result
+}
+
+/// The TufteCSS layouts an image can be placed in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum FigureLayout {
+    /// A regular `<figure>`, as wide as the main column.
+    #[default]
+    Main,
+    /// A `<figure class="fullwidth">`, spanning the whole page.
+    Fullwidth,
+    /// A margin figure, placed in the margin like a marginnote.
+    Margin,
+}
+
+/// Attributes set on an image with `{.fullwidth caption="..." link}`.
+#[derive(Debug, Default)]
+struct FigureAttrs {
+    layout: FigureLayout,
+    caption: Option<String>,
+    /// Where the image links to. A bare `link` links to the full-size image.
+    link: Option<String>,
+}
+
+impl FigureAttrs {
+    /// Parse the contents of an image's attribute block (without the braces).
+    fn parse(attrs: &str, src: &str) -> Self {
+        let mut figure_attrs = FigureAttrs::default();
+        let attr_rgx = Regex::new(r#"(\.[\w-]+)|([\w-]+)(?:=(?:"([^"]*)"|(\S+)))?"#).unwrap();
+
+        for caps in attr_rgx.captures_iter(attrs) {
+            if let Some(class) = caps.get(1) {
+                match class.as_str() {
+                    ".fullwidth" => figure_attrs.layout = FigureLayout::Fullwidth,
+                    ".margin" => figure_attrs.layout = FigureLayout::Margin,
+                    class => warn!("Ignoring unknown figure class `{class}`"),
+                }
+                continue;
+            }
+
+            let key = &caps[2];
+            let value = caps.get(3).or(caps.get(4)).map(|v| v.as_str().to_string());
+            match (key, value) {
+                ("caption", Some(caption)) => figure_attrs.caption = Some(caption),
+                ("link", Some(link)) => figure_attrs.link = Some(link),
+                ("link", None) => figure_attrs.link = Some(src.to_string()),
+                (key, _) => warn!("Ignoring unknown figure attribute `{key}`"),
+            }
+        }
+
+        figure_attrs
+    }
+}
+
 /// Converts all regular HTML images into TufteCSS image class (`<figure>` and `<figcaption>`).
-/// An image's `alt` becomes the content of `<figcaption>`.
+///
+/// An image's caption is taken from its `caption` attribute, its title, or its `alt`, in that
+/// order. The layout is chosen with `{.fullwidth}` or `{.margin}`, and `{link}` links the image
+/// to its full-size version. Images known to `process_image` get `srcset`s and dimensions,
+/// and every image is lazy-loaded.
 ///
 /// Note: images on marginnotes and sidenotes get ignored.
-pub(crate) fn convert_img_md_to_tufte(markdown: &str) -> String {
+pub(crate) fn convert_img_md_to_tufte(
+    markdown: &str,
+    process_image: &dyn Fn(&str) -> Option<ProcessedImage>,
+) -> String {
     let mut result = markdown.to_string();
     let mut protected_blocks = Vec::new();
     let mut counter = 0;
 
     // Protect images inside marginnotes and sidenotes by replacing them with placeholders.
-    let note_def_rgx = Regex::new(r"(?m)^(\[[\^*][^\]]+\]:\s*.*(?:\n[ \t]+.*)*)").unwrap();
+    let note_def_rgx =
+        Regex::new(r"(?m)^(\[[\^*][^\]]+\]:\s*.*(?:\n(?:[ \t]*\n)*[ \t]+.*)*)").unwrap();
     result = note_def_rgx
         .replace_all(&result, |caps: &regex::Captures| {
             let placeholder = format!("__PROTECTED_NOTE_{counter}__");
@@ -517,20 +1429,77 @@ pub(crate) fn convert_img_md_to_tufte(markdown: &str) -> String {
         })
         .to_string();
 
-    // Convert the remaining `<img>`es.
-    let img_rgx = Regex::new(r"!\[([^\]]*)\]\(([^)]+)\)").unwrap();
+    // Convert the remaining `<img>`es: `![alt](src "title"){attrs}`.
+    let mut margin_figure_ctr = 0;
+    let img_rgx =
+        Regex::new(r#"!\[([^\]]*)\]\(([^)\s]+)(?:\s+"([^"]*)")?\)(?:\{([^}]*)\})?"#).unwrap();
     result = img_rgx
         .replace_all(&result, |caps: &regex::Captures| {
             let alt_text = &caps[1];
             let src = &caps[2];
+            let title = caps.get(3).map(|t| t.as_str());
+            let attrs = caps
+                .get(4)
+                .map(|attrs| FigureAttrs::parse(attrs.as_str(), src))
+                .unwrap_or_default();
+
+            let caption = attrs
+                .caption
+                .as_deref()
+                .or(title)
+                .unwrap_or(alt_text);
+
+            let sizes = match attrs.layout {
+                FigureLayout::Main => None,
+                FigureLayout::Fullwidth => Some(FULLWIDTH_SIZES),
+                FigureLayout::Margin => Some(MARGIN_SIZES),
+            };
+            let mut img = match process_image(src) {
+                Some(image) => image.to_html(alt_text, sizes),
+                None if alt_text.is_empty() => format!(
+                    "<img src=\"{}\" loading=\"lazy\" decoding=\"async\">",
+                    html_escape::encode_double_quoted_attribute(src)
+                ),
+                None => format!(
+                    "<img src=\"{}\" alt=\"{}\" loading=\"lazy\" decoding=\"async\">",
+                    html_escape::encode_double_quoted_attribute(src),
+                    html_escape::encode_double_quoted_attribute(alt_text)
+                ),
+            };
+            if let Some(link) = &attrs.link {
+                img = format!(
+                    "<a href=\"{}\">{img}</a>",
+                    html_escape::encode_double_quoted_attribute(link)
+                );
+            }
 
-            if alt_text.is_empty() {
-                format!("<figure>\n<img src=\"{}\">\n</figure>", src)
-            } else {
-                format!(
-                    "<figure>\n<img src=\"{}\" alt=\"{}\">\n<figcaption>{}</figcaption>\n</figure>",
-                    src, alt_text, alt_text
-                )
+            match attrs.layout {
+                // Margin figures are inline, so they can't contain any block elements.
+                FigureLayout::Margin => {
+                    margin_figure_ctr += 1;
+                    let caption = if caption.is_empty() {
+                        String::new()
+                    } else {
+                        format!("<br/>{caption}")
+                    };
+                    format!(
+                        r#"<label for="mf-{margin_figure_ctr}" class="margin-toggle">⊕</label><input type="checkbox" id="mf-{margin_figure_ctr}" class="margin-toggle"/><span class="marginnote">{img}{caption}</span>"#
+                    )
+                }
+                layout => {
+                    let class = if layout == FigureLayout::Fullwidth {
+                        " class=\"fullwidth\""
+                    } else {
+                        ""
+                    };
+                    if caption.is_empty() {
+                        format!("<figure{class}>\n{img}\n</figure>")
+                    } else {
+                        format!(
+                            "<figure{class}>\n{img}\n<figcaption>{caption}</figcaption>\n</figure>"
+                        )
+                    }
+                }
             }
         })
         .to_string();
@@ -543,3 +1512,214 @@ pub(crate) fn convert_img_md_to_tufte(markdown: &str) -> String {
 
     result
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Run the parts of the Markdown pipeline that don't depend on `Tera`.
+    fn render(markdown: &str) -> String {
+        let markdown = convert_img_md_to_tufte(markdown, &|_| None);
+        let markdown = process_tufte_blocks(&markdown).unwrap();
+        let markdown = process_tufte_notes(&markdown, NoteStyle::Margin).unwrap();
+        let markdown = process_katex(&markdown).unwrap();
+        process_syntect(&markdown, &[], "src/themes/gruvbox-dark.tmTheme").unwrap()
+    }
+
+    #[test]
+    fn test_image_attributes_are_escaped() {
+        let html = render("![a \"quoted\" word](x.png){link=/a?b=1&c=2}");
+        assert!(html.contains("<img src=\"x.png\" alt=\"a &quot;quoted&quot; word\""));
+        assert!(html.contains("<a href=\"/a?b=1&amp;c=2\">"));
+    }
+
+    #[test]
+    fn test_epigraph() {
+        let html = render(
+            ":::epigraph\nI do not paint things, I paint only the differences between things.\n-- Henri Matisse, *Dessins*\n:::",
+        );
+
+        assert!(html.contains("<div class=\"epigraph\">"));
+        assert!(html.contains(
+            "<blockquote>\n<p>I do not paint things, I paint only the differences between things.</p>"
+        ));
+        assert!(html.contains("<footer>Henri Matisse, <em>Dessins</em></footer>"));
+    }
+
+    #[test]
+    fn test_epigraph_with_multiple_quotes() {
+        let html = render(":::epigraph\nFirst.\n-- One\n\nSecond.\n-- Two\n:::");
+
+        assert_eq!(html.matches("<blockquote>").count(), 2);
+        assert!(html.contains("<footer>One</footer>"));
+        assert!(html.contains("<footer>Two</footer>"));
+    }
+
+    #[test]
+    fn test_fullwidth_renders_markdown() {
+        let html = render(":::fullwidth\nSome **bold** text.\n:::");
+
+        assert!(html.contains("<div class=\"fullwidth\">"));
+        assert!(html.contains("<p>Some <strong>bold</strong> text.</p>"));
+    }
+
+    #[test]
+    fn test_newthought() {
+        let html = render(":newthought[In the beginning] there was nothing.");
+
+        assert!(
+            html.contains("<span class=\"newthought\">In the beginning</span> there was nothing.")
+        );
+    }
+
+    #[test]
+    fn test_newthought_ignored_in_inline_code() {
+        let html = render("Write `:newthought[words]` to start a new thought.");
+
+        assert!(html.contains("<code>:newthought[words]</code>"));
+        assert!(!html.contains("class=\"newthought\""));
+    }
+
+    #[test]
+    fn test_extensions_ignored_in_code_blocks() {
+        let html = render("```text\n:::epigraph\n:newthought[words]\n-- Nobody\n:::\n```");
+
+        assert!(!html.contains("class=\"epigraph\""));
+        assert!(!html.contains("class=\"newthought\""));
+        assert!(html.contains(":::epigraph"));
+        assert!(html.contains(":newthought[words]"));
+    }
+
+    #[test]
+    fn test_katex_inside_extensions() {
+        let html = render(
+            ":::epigraph\nEntropy is $\\log_2 6$ bits per throw.\n-- Someone\n:::\n\n:::fullwidth\n$$x^2$$\n:::\n\n:newthought[$k$ times] over.",
+        );
+
+        let epigraph = &html[html.find("class=\"epigraph\"").unwrap()..];
+        assert!(epigraph.contains("class=\"katex\""));
+        assert!(html.contains("class=\"katex-display\""));
+        assert!(html.contains("<span class=\"newthought\"><span class=\"katex\">"));
+        assert!(!html.contains('$'));
+    }
+
+    #[test]
+    fn test_margin_figure() {
+        let html = render("Some text ![A curve](curve.png \"The curve\"){.margin} more text.");
+
+        assert!(html.contains("<span class=\"marginnote\"><img src=\"curve.png\""));
+        assert!(html.contains("<br/>The curve</span>"));
+        assert!(!html.contains("<figure"));
+    }
+
+    #[test]
+    fn test_sidenote_renders_markdown() {
+        let html = render(
+            "Text[^a].\n\n[^a]: Has `a ]` and $x_1$,\n    and a list:\n\n    - one\n    - two",
+        );
+
+        let sidenote = &html[html.find("class=\"sidenote\"").unwrap()..];
+        assert!(sidenote.contains("<code>a ]</code>"));
+        assert!(sidenote.contains("class=\"katex\""));
+        assert!(sidenote.contains("<br/>• one<br/>• two</span>"));
+    }
+
+    #[test]
+    fn test_notes_as_footnotes() {
+        let markdown = "Text[^a] and[*b].\n\n[^a]: First *note*.\n\n[*b]: Second note.";
+        let html = process_tufte_notes(markdown, NoteStyle::Footnotes).unwrap();
+
+        assert!(html.contains(r##"<a href="#fn-sn-a" id="fnref-sn-a">1</a>"##));
+        assert!(html.contains(r##"<a href="#fn-mn-b" id="fnref-mn-b">2</a>"##));
+        assert!(html.contains(r#"<li id="fn-sn-a"><p>First <em>note</em>.</p>"#));
+        assert!(!html.contains("class=\"sidenote\""));
+    }
+
+    #[test]
+    fn test_repeated_footnote_refs() {
+        let markdown = "One[^a], two[^b], three[^a].\n\n[^a]: A.\n\n[^b]: B.";
+        let html = process_tufte_notes(markdown, NoteStyle::Footnotes).unwrap();
+
+        assert!(html.contains(r##"<a href="#fn-sn-a" id="fnref-sn-a">1</a>"##));
+        assert!(html.contains(r##"<a href="#fn-sn-b" id="fnref-sn-b">2</a>"##));
+        assert!(html.contains(r##"<a href="#fn-sn-a" id="fnref-sn-a-2">1</a>"##));
+        assert_eq!(html.matches(r#"<li id="fn-sn-a">"#).count(), 1);
+        assert!(
+            html.contains(
+                r##"<a href="#fnref-sn-a-2" class="footnote-backref">↩<sup>2</sup></a>"##
+            )
+        );
+
+        let html = process_tufte_notes(markdown, NoteStyle::Margin).unwrap();
+        assert!(html.contains(r#"id="sn-a""#) && html.contains(r#"id="sn-a-2""#));
+    }
+
+    #[test]
+    fn test_toc_tree() {
+        let entries = collect_headings(
+            "# Title\n\n## The `foo` *function*\n\n### Details {#details}\n\n## [Link](https://example.org)\n\n#### Deep",
+        );
+        let tree = build_toc_tree(entries.into_iter().filter(|entry| entry.level >= 2));
+
+        assert_eq!(tree.len(), 2);
+        assert_eq!(tree[0].id, "the-foo-function");
+        assert_eq!(tree[0].title_html, "The <code>foo</code> <em>function</em>");
+        assert_eq!(tree[0].children[0].id, "details");
+        assert_eq!(tree[1].title_html, "Link");
+        assert_eq!(tree[1].children[0].level, 4);
+    }
+
+    #[test]
+    fn test_heading_ids_survive_added_headings() {
+        let headings =
+            collect_headings("## Intro\n\n## The $x$ value\n\n## Intro\n\n## Outro {#end}");
+        // A shortcode added a heading before the others, and math was rendered.
+        let content =
+            "## Added\n\n## Intro\n\n## The <span>x</span> value\n\n## Intro\n\n## Outro {#end}";
+
+        let html = process_syntect(content, &headings, "src/themes/gruvbox-dark.tmTheme").unwrap();
+        let ids: Vec<&str> = Regex::new(r#"<h2 id="([^"]+)""#)
+            .unwrap()
+            .captures_iter(&html)
+            .map(|caps| caps.get(1).unwrap().as_str())
+            .collect();
+        assert_eq!(ids, ["added", "intro", "the-x-value", "intro-1", "end"]);
+    }
+
+    #[test]
+    fn test_summary() {
+        let markdown = "# Title\n\n{% include \"blog/toc.html\" %}\n\n![fig](img/a.png)\n\nFirst $x$ paragraph[^a].\n\n[^a]: A note.\n\n- a list\n\nSecond paragraph.\n\n<!-- more -->\n\nThird paragraph.";
+
+        let summary = extract_summary(markdown, None).unwrap().unwrap();
+        assert!(summary.starts_with("<p>First <span class=\"katex\">"));
+        assert!(summary.ends_with("<p>Second paragraph.</p>"));
+        assert!(!summary.contains("note") && !summary.contains("list"));
+
+        let markdown = markdown.replace("<!-- more -->", "");
+        let summary = extract_summary(&markdown, Some(1)).unwrap().unwrap();
+        assert!(summary.ends_with("paragraph.</p>") && !summary.contains("Second"));
+        assert_eq!(extract_summary(&markdown, None).unwrap(), None);
+    }
+
+    #[test]
+    fn test_summary_skips_containers() {
+        let markdown = ":::epigraph\nI paint only the differences.\n-- Henri Matisse\n:::\n\n:::fullwidth\nFirst paragraph.\n:::\n\nSecond paragraph.";
+
+        let summary = extract_summary(markdown, Some(2)).unwrap().unwrap();
+        assert_eq!(summary, "<p>First paragraph.</p>\n<p>Second paragraph.</p>");
+    }
+
+    #[test]
+    fn test_templating_only_touches_prose() {
+        let mut tera = Tera::default();
+        let mut ctx = Context::new();
+        ctx.insert("name", "world");
+        let markdown = "# Hi {{ name }} {#hi}\n\n`{{ name }}` and <b title=\"{{ name }}\">{{ name }}</b>\n\n```\n{{ name }} {% endraw %}\n```\n\n{% raw %}{{ name }} `{{ name }}`{% endraw %}";
+
+        let processed = process_tera_selectively(markdown, &mut tera, &ctx).unwrap();
+        assert_eq!(
+            processed,
+            "# Hi world {#hi}\n\n`{{ name }}` and <b title=\"{{ name }}\">world</b>\n\n```\n{{ name }} {% endraw %}\n```\n\n{{ name }} `{{ name }}`"
+        );
+    }
+}
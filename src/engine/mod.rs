@@ -1,4 +1,19 @@
+pub(crate) mod assets;
 pub(crate) mod config;
+pub(crate) mod csp;
+pub(crate) mod css;
+pub(crate) mod data;
 pub(crate) mod error;
+pub(crate) mod functions;
+pub(crate) mod html;
+pub(crate) mod images;
 pub(crate) mod markdown;
+pub(crate) mod quotes;
+pub(crate) mod redirect;
+pub(crate) mod search;
+pub(crate) mod seo;
+pub(crate) mod shortcodes;
+pub(crate) mod social;
+pub(crate) mod stats;
+pub(crate) mod templates;
 pub(crate) mod util;
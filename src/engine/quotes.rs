@@ -0,0 +1,214 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs,
+    path::Path,
+};
+
+use chrono::NaiveDate;
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+
+use crate::engine::{
+    error::EngineError,
+    util::{content_hash, slugify},
+};
+
+/// The quotes data file, deserialized.
+#[derive(Debug, Deserialize)]
+struct QuotesFile {
+    quotes: Vec<Quote>,
+}
+
+/// A quote shown in the footer of every page.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct Quote {
+    /// A stable identifier for pinning the quote to a page. Defaults to the slugified
+    /// author and a hash of the text, e.g. `mark-twain-3f2a9c1e`, so it doesn't change
+    /// when other quotes are added or removed.
+    #[serde(default)]
+    pub(crate) id: String,
+    /// The quote itself. A single newline is rendered as a line break.
+    pub(crate) text: String,
+    /// Who said or wrote it.
+    pub(crate) author: String,
+    /// The book, essay or speech it comes from.
+    pub(crate) work: Option<String>,
+    /// When it was said or written. Negative years are BC.
+    pub(crate) year: Option<i32>,
+    /// Where to read the source.
+    pub(crate) url: Option<String>,
+    /// Arbitrary tags, like a blog post's.
+    #[serde(default)]
+    pub(crate) tags: Vec<String>,
+}
+
+impl Quote {
+    /// The quote's text as HTML, with line breaks.
+    pub(crate) fn text_html(&self) -> String {
+        html_escape::encode_text(&self.text).replace('\n', "<br/>")
+    }
+
+    /// The quote's attribution as HTML: the author, linked to the source
+    /// if there's a URL, followed by the work and the year.
+    pub(crate) fn attribution_html(&self) -> String {
+        let author = html_escape::encode_text(&self.author);
+        let mut attribution = match &self.url {
+            Some(url) => format!(
+                "<a href=\"{}\">{author}</a>",
+                html_escape::encode_double_quoted_attribute(url)
+            ),
+            None => author.to_string(),
+        };
+        if let Some(work) = &self.work {
+            attribution.push_str(&format!(
+                ", <cite>{}</cite>",
+                html_escape::encode_text(work)
+            ));
+        }
+        match self.year {
+            Some(year) if year < 0 => attribution.push_str(&format!(", {} BC", -year)),
+            Some(year) => attribution.push_str(&format!(", {year}")),
+            None => {}
+        }
+
+        attribution
+    }
+
+    /// The quote as JSON, for templates and client-side scripts.
+    pub(crate) fn to_json(&self) -> Value {
+        json!({
+            "id": self.id,
+            "text": self.text_html(),
+            "author": self.author,
+            "work": self.work,
+            "year": self.year,
+            "url": self.url,
+            "tags": self.tags,
+            "attribution": self.attribution_html(),
+        })
+    }
+}
+
+/// The site's quotes, and how one is picked for each page.
+pub(crate) struct QuoteBook {
+    pub(crate) quotes: Vec<Quote>,
+    /// The build date, which seeds the selection.
+    date: NaiveDate,
+}
+
+impl QuoteBook {
+    pub(crate) fn new(quotes: Vec<Quote>, date: NaiveDate) -> Self {
+        Self { quotes, date }
+    }
+
+    /// Pick the quote for the page at `path`: the `pinned` one, if any, or
+    /// one seeded by the path and the build date. With the `random` quote mode,
+    /// the seeded quote is what readers without JavaScript see.
+    pub(crate) fn select(&self, path: &str, pinned: Option<&str>) -> Result<&Quote, EngineError> {
+        if let Some(id) = pinned {
+            return self
+                .quotes
+                .iter()
+                .find(|quote| quote.id == id)
+                .ok_or_else(|| EngineError::Quote(format!("{path} pins unknown quote `{id}`")));
+        }
+
+        let hash = content_hash(format!("{path}{}", self.date).as_bytes());
+        let seed = u64::from_str_radix(&hash[..16], 16).expect("a hex-encoded hash");
+
+        Ok(&self.quotes[(seed % self.quotes.len() as u64) as usize])
+    }
+
+    /// Quotes grouped by author, sorted by name, for the `/quotes/` page.
+    pub(crate) fn by_author(&self) -> Vec<Value> {
+        let mut groups: BTreeMap<&str, Vec<Value>> = BTreeMap::new();
+        for quote in &self.quotes {
+            groups
+                .entry(&quote.author)
+                .or_default()
+                .push(quote.to_json());
+        }
+
+        groups
+            .into_iter()
+            .map(|(author, quotes)| json!({"author": author, "id": slugify(author), "quotes": quotes}))
+            .collect()
+    }
+}
+
+/// Read and validate the quotes data file.
+///
+/// Every problem is logged before failing, so they can all be fixed at once.
+pub(crate) fn load_quotes(path: &Path) -> Result<Vec<Quote>, EngineError> {
+    let raw = fs::read_to_string(path)?;
+    let mut quotes = toml::from_str::<QuotesFile>(&raw)?.quotes;
+
+    // Derive missing ids from the author and the text, e.g. `mark-twain-3f2a9c1e`.
+    for quote in &mut quotes {
+        if quote.id.is_empty() {
+            let hash = content_hash(quote.text.trim().as_bytes());
+            quote.id = format!("{}-{}", slugify(&quote.author), &hash[..8]);
+        }
+    }
+
+    let mut problems = validate_quotes(&quotes);
+    if quotes.is_empty() {
+        problems.push("there are no quotes".to_string());
+    }
+    if !problems.is_empty() {
+        for problem in &problems {
+            error!("{}: {problem}", path.display());
+        }
+        return Err(EngineError::Quote(format!(
+            "{} problem(s) in {}",
+            problems.len(),
+            path.display()
+        )));
+    }
+    info!("Loaded {} quotes from {}", quotes.len(), path.display());
+
+    Ok(quotes)
+}
+
+/// Check quotes for duplicates, empty fields and stray whitespace.
+fn validate_quotes(quotes: &[Quote]) -> Vec<String> {
+    let mut problems = Vec::new();
+    let mut seen = HashSet::new();
+    let mut ids = HashSet::new();
+
+    for (i, quote) in quotes.iter().enumerate() {
+        let n = i + 1;
+        if !seen.insert(quote.text.trim().to_lowercase()) {
+            problems.push(format!("quote #{n} by {} is a duplicate", quote.author));
+        }
+
+        if !ids.insert(&quote.id) {
+            problems.push(format!("quote #{n} has a duplicate id `{}`", quote.id));
+        }
+
+        let mut fields = vec![("text", &quote.text), ("author", &quote.author)];
+        if let Some(work) = &quote.work {
+            fields.push(("work", work));
+        }
+        if let Some(url) = &quote.url {
+            fields.push(("url", url));
+        }
+        for (name, value) in fields {
+            if value.trim().is_empty() {
+                problems.push(format!("quote #{n} has an empty `{name}`"));
+            } else if value.trim() != value {
+                problems.push(format!(
+                    "quote #{n} has leading or trailing whitespace in `{name}`"
+                ));
+            } else if name != "text" && value.contains('\n') {
+                problems.push(format!("quote #{n} has a newline in `{name}`"));
+            }
+        }
+        if quote.text.contains("\n\n") {
+            problems.push(format!("quote #{n} has a blank line in `text`"));
+        }
+    }
+
+    problems
+}
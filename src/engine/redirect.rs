@@ -0,0 +1,258 @@
+use std::{collections::BTreeMap, fs, path::Path};
+
+use log::info;
+
+use crate::engine::{
+    config::{ServerKind, SiteConfig},
+    error::EngineError,
+    html::write_html,
+    markdown::PageMetadata,
+    util::clean_url,
+};
+
+/// Collect redirects from the `[redirects]` table and from every page's `aliases`.
+/// Chains of redirects are followed, so every redirect points at its final target.
+///
+/// Fails if the same path is redirected to two different targets, if a redirect
+/// would shadow an existing page, or if redirects loop.
+pub(crate) fn collect_redirects(
+    site_config: &SiteConfig,
+    pages: &[PageMetadata],
+) -> Result<BTreeMap<String, String>, EngineError> {
+    let mut redirects = site_config.redirects.clone();
+
+    for page in pages {
+        let (Some(path), Some(aliases)) = (&page.path, &page.aliases) else {
+            continue;
+        };
+        let target = clean_url(path);
+
+        for alias in aliases {
+            match redirects.get(alias) {
+                Some(existing) if *existing != target => {
+                    return Err(EngineError::Redirect(format!(
+                        "{alias} redirects to both {existing} and {target}"
+                    )));
+                }
+                _ => {
+                    redirects.insert(alias.clone(), target.clone());
+                }
+            }
+        }
+    }
+
+    // `/old`, `/old/` and `/old/index.html` are written to the same stub, so they're the same path.
+    let mut by_file: BTreeMap<String, (&String, &String)> = BTreeMap::new();
+    for (from, to) in &redirects {
+        match by_file.insert(stub_file(from)?, (from, to)) {
+            Some((other, other_to)) if other_to != to => {
+                return Err(EngineError::Redirect(format!(
+                    "{other} and {from} are the same path, but redirect to {other_to} and {to}"
+                )));
+            }
+            _ => {}
+        }
+    }
+
+    // A redirect must never overwrite a real page.
+    for page in pages {
+        if let Some(path) = &page.path
+            && let Some((from, _)) = by_file.get(&stub_file(path)?)
+        {
+            return Err(EngineError::Redirect(format!(
+                "{from} would overwrite the page at {path}"
+            )));
+        }
+    }
+
+    // Follow chains, so `/a -> /b -> /c` becomes `/a -> /c` and `/b -> /c`.
+    let mut resolved = BTreeMap::new();
+    for (from, to) in &redirects {
+        let mut target = to;
+        let mut seen = vec![stub_file(from)?];
+        while target.starts_with('/')
+            && let Ok(file) = stub_file(target)
+            && let Some((_, next)) = by_file.get(&file)
+        {
+            if seen.contains(&file) {
+                return Err(EngineError::Redirect(format!(
+                    "{from} is part of a redirect loop"
+                )));
+            }
+            seen.push(file);
+            target = next;
+        }
+        resolved.insert(from.clone(), target.clone());
+    }
+
+    Ok(resolved)
+}
+
+/// The file a redirect stub for `from` is written to, relative to the build directory.
+///
+/// `/old/` and `/old` become `old/index.html`, `/old.html` is kept as-is.
+fn stub_file(from: &str) -> Result<String, EngineError> {
+    if !from.starts_with('/') {
+        return Err(EngineError::Redirect(format!(
+            "redirect source {from} must be an absolute path"
+        )));
+    }
+    // `.` and `..` could point outside the build directory.
+    if from
+        .split('/')
+        .any(|segment| segment == "." || segment == "..")
+    {
+        return Err(EngineError::Redirect(format!(
+            "redirect source {from} must not have `.` or `..` segments"
+        )));
+    }
+
+    let relative = from.trim_start_matches('/');
+    Ok(if relative.is_empty() || from.ends_with('/') {
+        format!("{relative}index.html")
+    } else if relative.ends_with(".html") {
+        relative.to_string()
+    } else {
+        format!("{relative}/index.html")
+    })
+}
+
+/// Write a meta-refresh HTML stub for every redirect into the build directory.
+pub(crate) fn write_redirect_stubs(
+    site_config: &SiteConfig,
+    redirects: &BTreeMap<String, String>,
+    build_dir: &str,
+) -> Result<(), EngineError> {
+    for (from, to) in redirects {
+        let stub_path = Path::new(build_dir).join(stub_file(from)?);
+
+        // Relative targets are resolved against the site's canonical name.
+        let canonical = if to.starts_with('/') {
+            format!("https://{}{to}", site_config.cname)
+        } else {
+            to.clone()
+        };
+        let to = html_escape::encode_double_quoted_attribute(to);
+        let canonical = html_escape::encode_double_quoted_attribute(&canonical);
+
+        let stub = format!(
+            r#"<!doctype html>
+<html>
+    <head>
+        <meta charset="utf-8" />
+        <title>Redirecting to {to}</title>
+        <link rel="canonical" href="{canonical}" />
+        <meta name="robots" content="noindex" />
+        <meta http-equiv="refresh" content="0; url={to}" />
+    </head>
+    <body>
+        <a href="{to}">Click here if you are not redirected.</a>
+    </body>
+</html>
+"#
+        );
+
+        write_html(site_config, &stub_path, &stub)?;
+        info!("Wrote redirect stub {} -> {to}", stub_path.display());
+    }
+
+    Ok(())
+}
+
+/// Write the redirects as a server configuration snippet, if a server is configured.
+pub(crate) fn write_server_snippet(
+    site_config: &SiteConfig,
+    redirects: &BTreeMap<String, String>,
+    build_dir: &str,
+) -> Result<(), EngineError> {
+    let Some(server) = site_config.server else {
+        return Ok(());
+    };
+
+    let (file_name, snippet) = match server {
+        ServerKind::Netlify => (
+            "_redirects",
+            redirects
+                .iter()
+                .map(|(from, to)| format!("{from} {to} 301\n"))
+                .collect::<String>(),
+        ),
+        ServerKind::Caddy => (
+            "redirects.caddy",
+            redirects
+                .iter()
+                .map(|(from, to)| format!("redir {from} {to} permanent\n"))
+                .collect::<String>(),
+        ),
+    };
+
+    let snippet_path = Path::new(build_dir).join(file_name);
+    fs::create_dir_all(build_dir)?;
+    fs::write(&snippet_path, snippet)?;
+    info!(
+        "Wrote {} redirects to {}",
+        redirects.len(),
+        snippet_path.display()
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn site_config(redirects: &str) -> SiteConfig {
+        toml::from_str(&format!(
+            r#"
+            cname = "example.com"
+            title = "Example"
+            description = "An example site"
+            author = "Someone"
+            content_path = "content"
+            build_path = "build"
+            quotes_path = "quotes.toml"
+
+            [redirects]
+            {redirects}
+            "#
+        ))
+        .unwrap()
+    }
+
+    fn page(path: &str) -> PageMetadata {
+        PageMetadata {
+            path: Some(path.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_redirect_shadowing_a_page() {
+        let pages = [page("/blog/post/")];
+        for from in ["/blog/post", "/blog/post/", "/blog/post/index.html"] {
+            let config = site_config(&format!("\"{from}\" = \"/blog/new/\""));
+            assert!(collect_redirects(&config, &pages).is_err(), "{from}");
+        }
+    }
+
+    #[test]
+    fn test_redirect_trailing_slash() {
+        let same = site_config("\"/old\" = \"/new/\"\n\"/old/\" = \"/new/\"");
+        assert_eq!(collect_redirects(&same, &[]).unwrap().len(), 2);
+
+        let different = site_config("\"/old\" = \"/new/\"\n\"/old/\" = \"/other/\"");
+        assert!(collect_redirects(&different, &[]).is_err());
+    }
+
+    #[test]
+    fn test_redirect_chain() {
+        let config = site_config("\"/a/\" = \"/b\"\n\"/b/\" = \"/c/\"");
+        let redirects = collect_redirects(&config, &[page("/c/")]).unwrap();
+        assert_eq!(redirects["/a/"], "/c/");
+        assert_eq!(redirects["/b/"], "/c/");
+
+        let looping = site_config("\"/a/\" = \"/b/\"\n\"/b/\" = \"/a/\"");
+        assert!(collect_redirects(&looping, &[]).is_err());
+    }
+}
@@ -0,0 +1,90 @@
+use std::{fs, path::Path};
+
+use chrono::NaiveDate;
+use log::info;
+use serde::Serialize;
+
+use crate::engine::{
+    config::SiteConfig,
+    error::EngineError,
+    markdown::{PageMetadata, collect_headings},
+    stats::extract_prose,
+    util::clean_url,
+};
+
+/// The search index, written to the build directory.
+const SEARCH_INDEX_FILE: &str = "search.json";
+
+/// A page in the search index.
+#[derive(Debug, Serialize)]
+struct SearchEntry {
+    path: String,
+    title: String,
+    description: String,
+    date: NaiveDate,
+    tags: Vec<String>,
+    headings: Vec<String>,
+    /// The page's prose, without code, math or markup.
+    body: String,
+}
+
+/// Write the search index of every page in the configured sections, or that opted in
+/// with `search = true`, to the build directory. Pages can opt out with `search = false`.
+///
+/// Does nothing if search isn't configured.
+pub(crate) fn write_search_index(
+    site_config: &SiteConfig,
+    page_index: &[PageMetadata],
+) -> Result<(), EngineError> {
+    let Some(search) = &site_config.search else {
+        return Ok(());
+    };
+
+    let mut entries = Vec::new();
+    for page in page_index {
+        let (Some(path), Some(source)) = (&page.path, &page.source) else {
+            continue;
+        };
+        let path = clean_url(path);
+        let in_section = search
+            .sections
+            .iter()
+            .any(|section| path.starts_with(section));
+        if !page.search.unwrap_or(in_section) {
+            continue;
+        }
+
+        let content = fs::read_to_string(source)?;
+        let Some((_, markdown)) = matter::matter(&content) else {
+            return Err(EngineError::NoMatter);
+        };
+        entries.push(SearchEntry {
+            path,
+            title: page.title.clone(),
+            description: page.description.clone(),
+            date: page.date,
+            tags: page.tags.clone().unwrap_or_default(),
+            headings: collect_headings(&markdown)
+                .into_iter()
+                .map(|heading| heading.title)
+                .collect(),
+            body: extract_prose(&markdown, None)?
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" "),
+        });
+    }
+    entries.sort_by_key(|entry| std::cmp::Reverse(entry.date));
+
+    let index_path = Path::new(&site_config.build_path).join(SEARCH_INDEX_FILE);
+    let index_json = serde_json::to_string(&entries).expect("search entries serialize");
+    fs::write(&index_path, &index_json)?;
+    info!(
+        "Wrote {} pages to the search index {} ({} bytes)",
+        entries.len(),
+        index_path.display(),
+        index_json.len()
+    );
+
+    Ok(())
+}
@@ -0,0 +1,171 @@
+use std::path::Path;
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::engine::{
+    config::SiteConfig, error::EngineError, markdown::PageMetadata, util::clean_url,
+};
+
+/// Pages under `/blog/` that are listings rather than posts.
+/// Other pages can say so with `listing = true`.
+const BLOG_LISTINGS: [&str; 3] = ["/blog/", "/blog/tags/", "/blog/archive/"];
+
+/// Search engine and social media metadata for a page, emitted by `base.html`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub(crate) struct PageSeo {
+    /// The page's absolute, clean URL.
+    pub(crate) canonical: String,
+    /// The page's description, or the site's if the page has none.
+    pub(crate) description: String,
+    /// The Open Graph type: `article` for blog posts, `website` otherwise.
+    pub(crate) og_type: String,
+    /// The absolute URL of the preview image, if any.
+    pub(crate) image: Option<String>,
+    /// The Twitter card type, large if there's a preview image.
+    pub(crate) twitter_card: String,
+    /// `BlogPosting` structured data, for blog posts only.
+    pub(crate) json_ld: Option<String>,
+}
+
+/// Compute a page's [`PageSeo`] once its `path` is known.
+///
+/// The preview image is the frontmatter's `image` or, failing that,
+/// the first image in the page's `markdown`.
+pub(crate) fn build_seo(
+    site_config: &SiteConfig,
+    metadata: &PageMetadata,
+    markdown: &str,
+) -> Result<PageSeo, EngineError> {
+    let path = metadata.path.as_deref().unwrap_or("/");
+    let canonical = absolute_url(site_config, &clean_url(path));
+    let description = if metadata.description.is_empty() {
+        site_config.description.clone()
+    } else {
+        metadata.description.clone()
+    };
+
+    let image = match &metadata.image {
+        Some(image) => Some(image.clone()),
+        None => first_image(markdown)?,
+    }
+    .map(|src| resolve_image(site_config, path, &src));
+
+    let is_post = is_blog_post(metadata, path);
+    let json_ld = if is_post {
+        let mut posting = json!({
+            "@context": "https://schema.org",
+            "@type": "BlogPosting",
+            "headline": metadata.title,
+            "description": description,
+            "url": canonical,
+            "mainEntityOfPage": canonical,
+            "datePublished": metadata.date.to_string(),
+            "dateModified": metadata.edited.clone().unwrap_or_else(|| metadata.date.to_string()),
+            "keywords": metadata.tags.clone().unwrap_or_default().join(", "),
+            "author": {
+                "@type": "Person",
+                "name": site_config.author,
+                "url": absolute_url(site_config, "/"),
+            },
+        });
+        if let Some(image) = &image {
+            posting["image"] = json!(image);
+        }
+        // Keep `</script>` in titles or descriptions from closing the script tag.
+        Some(posting.to_string().replace("</", "<\\/"))
+    } else {
+        None
+    };
+
+    Ok(PageSeo {
+        canonical,
+        description,
+        og_type: if is_post { "article" } else { "website" }.to_string(),
+        twitter_card: if image.is_some() {
+            "summary_large_image"
+        } else {
+            "summary"
+        }
+        .to_string(),
+        image,
+        json_ld,
+    })
+}
+
+/// Whether the page at `path` is a blog post, rather than a listing or a standalone page.
+pub(crate) fn is_blog_post(metadata: &PageMetadata, path: &str) -> bool {
+    let path = clean_url(path);
+    path.starts_with("/blog/")
+        && !BLOG_LISTINGS.contains(&path.as_str())
+        && metadata.listing != Some(true)
+}
+
+/// Turn a site-relative path into an absolute URL.
+fn absolute_url(site_config: &SiteConfig, path: &str) -> String {
+    format!("https://{}{}", site_config.cname, path)
+}
+
+/// Find the `src` of the first image in some Markdown.
+fn first_image(markdown: &str) -> Result<Option<String>, EngineError> {
+    let img_rgx = Regex::new(r#"!\[[^\]]*\]\(([^)\s]+)"#)?;
+
+    Ok(img_rgx
+        .captures(markdown)
+        .map(|captures| captures[1].to_string()))
+}
+
+/// Resolve an image's `src` against the page at `page_path` into an absolute URL.
+fn resolve_image(site_config: &SiteConfig, page_path: &str, src: &str) -> String {
+    if src.contains("://") {
+        return src.to_string();
+    }
+    if src.starts_with('/') {
+        return absolute_url(site_config, src);
+    }
+
+    let page_dir = Path::new(page_path).parent().unwrap_or(Path::new("/"));
+    let src = src.trim_start_matches("./");
+    absolute_url(site_config, &page_dir.join(src).display().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_listings_are_not_posts() {
+        let site_config: SiteConfig = toml::from_str(
+            r#"
+            cname = "example.com"
+            title = "Example"
+            description = "An example site"
+            author = "Someone"
+            content_path = "content"
+            build_path = "build"
+            quotes_path = "quotes.toml"
+            "#,
+        )
+        .unwrap();
+        let page = |path: &str, listing| PageMetadata {
+            title: "Blog Archive".to_string(),
+            path: Some(path.to_string()),
+            listing,
+            ..Default::default()
+        };
+
+        let post = build_seo(&site_config, &page("/blog/post/index.html", None), "").unwrap();
+        assert_eq!(post.og_type, "article");
+        assert!(post.json_ld.unwrap().contains("BlogPosting"));
+
+        for listing in [
+            page("/blog/archive/index.html", None),
+            page("/blog/listing/index.html", Some(true)),
+        ] {
+            let seo = build_seo(&site_config, &listing, "").unwrap();
+            assert_eq!(seo.og_type, "website");
+            assert!(seo.json_ld.is_none());
+        }
+    }
+}
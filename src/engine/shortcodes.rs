@@ -0,0 +1,298 @@
+use std::collections::HashSet;
+
+use log::debug;
+use pulldown_cmark::{Parser, html};
+use regex::{Captures, Regex};
+use serde_json::{Map, Number, Value};
+use tera::{Context, Tera};
+
+use crate::engine::{error::EngineError, markdown::MD_OPTIONS};
+
+/// Where shortcode templates live, relative to the templates directory.
+const SHORTCODES_DIR: &str = "shortcodes/";
+/// Protected shortcodes are wrapped in these, so the `Tera` pass over the page leaves them be.
+const RAW_START: &str = "{% raw %}";
+const RAW_END: &str = "{% endraw %}";
+
+/// Resolve shortcodes in some Markdown before it's rendered as a `Tera` template.
+///
+/// Inline shortcodes look like `{{ youtube(id="...") }}`; block shortcodes wrap a
+/// Markdown body, which is rendered to HTML and passed to the template as `body`:
+///
+/// ```text
+/// {% note(kind="warning") %}
+/// Some *Markdown*.
+/// {% end %}
+/// ```
+///
+/// Each shortcode is rendered from `shortcodes/<name>.html` with its arguments and
+/// `tera_ctx`. Shortcodes inside code are left alone, and errors point to the line
+/// in the source file, `line_offset` being the number of lines before the Markdown.
+/// With `protect`, for pages rendered as templates, the output is wrapped in `{% raw %}`.
+pub(crate) fn process_shortcodes(
+    markdown: &str,
+    tera: &Tera,
+    tera_ctx: &Context,
+    line_offset: usize,
+    protect: bool,
+) -> Result<String, EngineError> {
+    if !markdown.contains("{{") && !markdown.contains("{%") {
+        return Ok(markdown.to_string());
+    }
+    let names: HashSet<&str> = tera
+        .get_template_names()
+        .filter_map(|name| name.strip_prefix(SHORTCODES_DIR)?.strip_suffix(".html"))
+        .collect();
+
+    let inline_rgx = Regex::new(r"\{\{\s*(\w+)\((.*?)\)\s*\}\}")?;
+    let block_start_rgx = Regex::new(r"^\s*\{%\s*(\w+)\((.*?)\)\s*%\}\s*$")?;
+    let block_end_rgx = Regex::new(r"^\s*\{%\s*end\s*%\}\s*$")?;
+
+    let shortcodes = Shortcodes {
+        tera,
+        tera_ctx,
+        names: &names,
+        protect,
+    };
+
+    let mut result = String::new();
+    let mut lines = markdown.lines().enumerate();
+    let mut in_fence = false;
+    while let Some((i, line)) = lines.next() {
+        let line_number = line_offset + i + 1;
+
+        if line.trim_start().starts_with("```") {
+            in_fence = !in_fence;
+        }
+        if in_fence {
+            result.push_str(line);
+            result.push('\n');
+            continue;
+        }
+
+        // Block shortcodes take their own lines, up to the matching `{% end %}`.
+        if let Some(captures) = block_start_rgx.captures(line) {
+            let name = &captures[1];
+            let args =
+                parse_args(&captures[2]).map_err(|e| shortcode_error(line_number, name, &e))?;
+
+            let mut body = Vec::new();
+            let mut depth = 1;
+            let mut body_fence = false;
+            for (_, body_line) in lines.by_ref() {
+                if body_line.trim_start().starts_with("```") {
+                    body_fence = !body_fence;
+                }
+                if !body_fence && block_start_rgx.is_match(body_line) {
+                    depth += 1;
+                } else if !body_fence && block_end_rgx.is_match(body_line) {
+                    depth -= 1;
+                    if depth == 0 {
+                        break;
+                    }
+                }
+                body.push(body_line);
+            }
+            if depth != 0 {
+                return Err(shortcode_error(line_number, name, "missing `{% end %}`"));
+            }
+
+            // Nested shortcodes are resolved before the body is rendered,
+            // and only need protecting once, as part of this one.
+            let body = process_shortcodes(&body.join("\n"), tera, tera_ctx, line_number, false)?;
+            let mut body_html = String::new();
+            html::push_html(&mut body_html, Parser::new_ext(&body, MD_OPTIONS));
+
+            let rendered = shortcodes
+                .render(name, args, Some(&body_html))
+                .map_err(|e| shortcode_error(line_number, name, &e))?;
+            result.push_str(&format!("\n{rendered}\n\n"));
+            continue;
+        }
+        if block_end_rgx.is_match(line) {
+            return Err(shortcode_error(
+                line_number,
+                "end",
+                "`{% end %}` without a block shortcode",
+            ));
+        }
+
+        // Inline shortcodes, outside inline code. Unknown names are left to `Tera`, as functions.
+        let mut error = None;
+        let processed = line
+            .split_inclusive('`')
+            .enumerate()
+            .map(|(j, segment)| {
+                if j % 2 == 1 {
+                    return segment.to_string();
+                }
+                inline_rgx
+                    .replace_all(segment, |captures: &Captures| {
+                        let name = &captures[1];
+                        if !names.contains(name) {
+                            return captures[0].to_string();
+                        }
+                        parse_args(&captures[2])
+                            .and_then(|args| shortcodes.render(name, args, None))
+                            .unwrap_or_else(|e| {
+                                error.get_or_insert(shortcode_error(line_number, name, &e));
+                                String::new()
+                            })
+                    })
+                    .to_string()
+            })
+            .collect::<String>();
+        if let Some(error) = error {
+            return Err(error);
+        }
+        result.push_str(&processed);
+        result.push('\n');
+    }
+
+    Ok(result)
+}
+
+/// The shortcode templates available to a page.
+struct Shortcodes<'a> {
+    tera: &'a Tera,
+    tera_ctx: &'a Context,
+    names: &'a HashSet<&'a str>,
+    protect: bool,
+}
+
+impl Shortcodes<'_> {
+    /// Render a shortcode, protecting its output from the `Tera` pass over the page if needed.
+    fn render(
+        &self,
+        name: &str,
+        args: Map<String, Value>,
+        body: Option<&str>,
+    ) -> Result<String, String> {
+        if !self.names.contains(name) {
+            return Err(format!(
+                "unknown shortcode, expected a template at `{SHORTCODES_DIR}{name}.html`"
+            ));
+        }
+        debug!("Rendering shortcode `{name}`");
+
+        let mut ctx = self.tera_ctx.clone();
+        for (key, value) in args {
+            ctx.insert(key, &value);
+        }
+        if let Some(body) = body {
+            ctx.insert("body", body);
+        }
+        let rendered = self
+            .tera
+            .render(&format!("{SHORTCODES_DIR}{name}.html"), &ctx)
+            .map_err(|e| match std::error::Error::source(&e) {
+                Some(source) => format!("{e}: {source}"),
+                None => e.to_string(),
+            })?;
+
+        // Blank lines would end the HTML block in the Markdown around it.
+        let rendered = rendered
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        Ok(if self.protect {
+            format!("{RAW_START}{rendered}{RAW_END}")
+        } else {
+            rendered
+        })
+    }
+}
+
+/// Parse shortcode arguments like `id="abc", start=30, autoplay=true`.
+fn parse_args(args: &str) -> Result<Map<String, Value>, String> {
+    let arg_rgx = Regex::new(
+        r#"^\s*(\w+)\s*=\s*("(?:[^"\\]|\\.)*"|'[^']*'|-?\d+(?:\.\d+)?|true|false)\s*(?:,|$)"#,
+    )
+    .map_err(|e| e.to_string())?;
+
+    let mut parsed = Map::new();
+    let mut rest = args.trim();
+    while !rest.is_empty() {
+        let captures = arg_rgx
+            .captures(rest)
+            .ok_or_else(|| format!("can't parse arguments `{rest}`"))?;
+        let raw = &captures[2];
+        let value = if raw.starts_with('"') {
+            Value::String(
+                serde_json::from_str::<String>(raw)
+                    .map_err(|e| format!("bad string {raw}: {e}"))?,
+            )
+        } else if let Some(raw) = raw.strip_prefix('\'') {
+            Value::String(raw.trim_end_matches('\'').to_string())
+        } else if raw == "true" || raw == "false" {
+            Value::Bool(raw == "true")
+        } else if let Ok(int) = raw.parse::<i64>() {
+            Value::Number(int.into())
+        } else {
+            raw.parse::<f64>()
+                .ok()
+                .and_then(Number::from_f64)
+                .map(Value::Number)
+                .ok_or_else(|| format!("bad number {raw}"))?
+        };
+        parsed.insert(captures[1].to_string(), value);
+        rest = rest[captures[0].len()..].trim_start();
+    }
+
+    Ok(parsed)
+}
+
+fn shortcode_error(line: usize, name: &str, message: &str) -> EngineError {
+    EngineError::Shortcode(format!("line {line}: `{name}`: {message}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tera() -> Tera {
+        let mut tera = Tera::default();
+        tera.add_raw_templates(vec![
+            ("shortcodes/badge.html", "<span>{{ label }}-{{ n }}</span>"),
+            (
+                "shortcodes/box.html",
+                "<div class=\"{{ kind }}\">\n\n{{ body | safe }}</div>",
+            ),
+        ])
+        .unwrap();
+        tera
+    }
+
+    #[test]
+    fn test_shortcodes() {
+        let markdown = "A {{ badge(label=\"new\", n=2) }} and `{{ badge(label=\"code\", n=1) }}`.\n\n{% box(kind='tip') %}\n*Body* {{ badge(label=\"inner\", n=3) }}\n{% end %}\n\n```\n{{ badge(label=\"fenced\", n=4) }}\n```";
+        let html = process_shortcodes(markdown, &tera(), &Context::new(), 0, true).unwrap();
+
+        assert!(html.starts_with("A {% raw %}<span>new-2</span>{% endraw %} and `{{ badge("));
+        assert!(html.contains(
+            "{% raw %}<div class=\"tip\">\n<p><em>Body</em> <span>inner-3</span></p>\n</div>{% endraw %}"
+        ));
+        assert!(html.contains("{{ badge(label=\"fenced\", n=4) }}"));
+
+        let html = process_shortcodes(markdown, &tera(), &Context::new(), 0, false).unwrap();
+        assert!(html.starts_with("A <span>new-2</span> and `{{ badge("));
+        assert!(!html.contains(RAW_START));
+    }
+
+    #[test]
+    fn test_shortcode_errors() {
+        let error = |markdown| {
+            process_shortcodes(markdown, &tera(), &Context::new(), 6, false)
+                .unwrap_err()
+                .to_string()
+        };
+
+        assert!(
+            error("Text\n\n{% box(kind=\"tip\") %}\nNo end.").contains("line 9: `box`: missing")
+        );
+        assert!(error("{{ badge(label=new) }}").contains("line 7: `badge`: can't parse"));
+        assert!(error("{% nope() %}\n{% end %}").contains("unknown shortcode"));
+    }
+}
@@ -0,0 +1,181 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use image::{RgbImage, codecs::jpeg::JpegEncoder};
+use log::{debug, info};
+use resvg::{tiny_skia, usvg};
+use tera::{Context, Tera};
+use walkdir::WalkDir;
+
+use crate::engine::{
+    config::{SiteConfig, SocialConfig},
+    error::EngineError,
+    markdown::PageMetadata,
+    util::content_hash,
+};
+
+/// The SVG template previews are rendered from, relative to the templates directory.
+const PREVIEW_TEMPLATE: &str = "social/preview.svg";
+/// The file name of the preview, written next to the page.
+const PREVIEW_FILE: &str = "social.jpg";
+/// The preview's JPEG quality, from 1 to 100. The banner is a photo,
+/// which would make a PNG too heavy for crawlers that cap image sizes.
+const PREVIEW_QUALITY: u8 = 85;
+/// Roughly how many characters of the title fit in a line.
+const TITLE_LINE_CHARS: usize = 28;
+/// How many lines of the title fit in the preview.
+const TITLE_MAX_LINES: usize = 3;
+
+/// Renders per-post social media preview images from an SVG template,
+/// caching the JPEGs by the rendered SVG's content hash.
+pub(crate) struct SocialPreviews {
+    config: Option<SocialConfig>,
+    /// The site's name, shown in the corner.
+    site: String,
+    /// The banner, embedded as a `data:` URI.
+    banner: String,
+    options: usvg::Options<'static>,
+}
+
+impl SocialPreviews {
+    pub(crate) fn new(site_config: &SiteConfig, tera: &mut Tera) -> Result<Self, EngineError> {
+        let config = site_config.social.clone();
+        let site = site_config.cname.clone();
+        let mut options = usvg::Options::default();
+        let Some(social) = &config else {
+            return Ok(Self {
+                config,
+                site,
+                banner: String::new(),
+                options,
+            });
+        };
+
+        fs::create_dir_all(&social.cache_path)?;
+        let template = site_config
+            .find_template(PREVIEW_TEMPLATE)
+            .ok_or_else(|| EngineError::Preview(format!("no `{PREVIEW_TEMPLATE}` template")))?;
+        tera.add_template_file(template, Some(PREVIEW_TEMPLATE))?;
+
+        for entry in WalkDir::new(&social.font_path)
+            .into_iter()
+            .filter_map(|e| e.ok())
+        {
+            if matches!(
+                entry.path().extension().and_then(|s| s.to_str()),
+                Some("ttf" | "otf")
+            ) {
+                options.fontdb_mut().load_font_data(fs::read(entry.path())?);
+            }
+        }
+        for face in options.fontdb.faces() {
+            debug!("Loaded preview font {:?}", face.families);
+        }
+        info!("Loaded preview fonts from {}", social.font_path);
+
+        let mime = match Path::new(&social.banner)
+            .extension()
+            .and_then(|s| s.to_str())
+        {
+            Some("png") => "image/png",
+            Some("webp") => "image/webp",
+            _ => "image/jpeg",
+        };
+        let banner = format!(
+            "data:{mime};base64,{}",
+            STANDARD.encode(fs::read(&social.banner)?)
+        );
+
+        Ok(Self {
+            config,
+            site,
+            banner,
+            options,
+        })
+    }
+
+    /// Render the preview for the page described by `metadata` into `page_build_dir`.
+    ///
+    /// Returns the preview's URL relative to the page, or `None` if previews are disabled.
+    pub(crate) fn render(
+        &self,
+        tera: &Tera,
+        metadata: &PageMetadata,
+        page_build_dir: &Path,
+    ) -> Result<Option<String>, EngineError> {
+        let Some(config) = &self.config else {
+            return Ok(None);
+        };
+
+        let mut ctx = Context::new();
+        ctx.insert("banner", &self.banner);
+        ctx.insert("title_lines", &wrap_title(&metadata.title));
+        ctx.insert("date", &metadata.date.to_string());
+        ctx.insert("tags", &metadata.tags.clone().unwrap_or_default());
+        ctx.insert("site", &self.site);
+        let svg = tera.render(PREVIEW_TEMPLATE, &ctx)?;
+
+        let cache_path = PathBuf::from(&config.cache_path)
+            .join(format!("{}.jpg", &content_hash(svg.as_bytes())[..16]));
+        if cache_path.exists() {
+            debug!("Using cached social preview {}", cache_path.display());
+        } else {
+            let tree = usvg::Tree::from_str(&svg, &self.options)
+                .map_err(|e| EngineError::Preview(e.to_string()))?;
+            let size = tree.size().to_int_size();
+            let mut pixmap = tiny_skia::Pixmap::new(size.width(), size.height())
+                .ok_or_else(|| EngineError::Preview("empty preview template".to_string()))?;
+            resvg::render(&tree, tiny_skia::Transform::default(), &mut pixmap.as_mut());
+
+            // JPEG has no alpha, and the template's background is opaque anyway.
+            let rgb: Vec<u8> = pixmap
+                .pixels()
+                .iter()
+                .flat_map(|pixel| {
+                    let pixel = pixel.demultiply();
+                    [pixel.red(), pixel.green(), pixel.blue()]
+                })
+                .collect();
+            let image = RgbImage::from_raw(size.width(), size.height(), rgb)
+                .ok_or_else(|| EngineError::Preview("invalid preview size".to_string()))?;
+            image.write_with_encoder(JpegEncoder::new_with_quality(
+                fs::File::create(&cache_path)?,
+                PREVIEW_QUALITY,
+            ))?;
+            info!("Generated social preview {}", cache_path.display());
+        }
+
+        fs::create_dir_all(page_build_dir)?;
+        fs::copy(&cache_path, page_build_dir.join(PREVIEW_FILE))?;
+
+        Ok(Some(PREVIEW_FILE.to_string()))
+    }
+}
+
+/// Break a title into lines that fit the preview, ending with an ellipsis if it's too long.
+fn wrap_title(title: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    let mut line = String::new();
+    for word in title.split_whitespace() {
+        if !line.is_empty() && line.chars().count() + 1 + word.chars().count() > TITLE_LINE_CHARS {
+            lines.push(std::mem::take(&mut line));
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(word);
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+
+    if lines.len() > TITLE_MAX_LINES {
+        lines.truncate(TITLE_MAX_LINES);
+        lines[TITLE_MAX_LINES - 1].push('…');
+    }
+
+    lines
+}
@@ -0,0 +1,149 @@
+use log::info;
+use pulldown_cmark::{Event, Options, Parser, Tag, TagEnd};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::engine::{error::EngineError, markdown::PageMetadata};
+
+/// The average reading speed, used to estimate reading time.
+const WORDS_PER_MINUTE: usize = 200;
+
+/// Content statistics derived from a page's Markdown.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub(crate) struct PageStats {
+    /// Words in prose, excluding code and math.
+    pub(crate) words: usize,
+    /// Estimated reading time, in minutes.
+    pub(crate) reading_time: usize,
+    /// Fenced and indented code blocks.
+    pub(crate) code_blocks: usize,
+    /// Inline (`$ <expr> $`) and display (`$$ <expr> $$`) equations.
+    pub(crate) equations: usize,
+    /// Images, whether rendered as figures or in the margin.
+    pub(crate) figures: usize,
+}
+
+/// Compute a page's [`PageStats`] from its raw Markdown (without frontmatter).
+pub(crate) fn compute_stats(markdown: &str) -> Result<PageStats, EngineError> {
+    let mut stats = PageStats::default();
+    let text = extract_prose(markdown, Some(&mut stats))?;
+
+    stats.words = text
+        .split_whitespace()
+        .filter(|word| word.chars().any(char::is_alphanumeric))
+        .count();
+    stats.reading_time = reading_time(stats.words);
+
+    Ok(stats)
+}
+
+/// Estimate the reading time of some number of words, in minutes.
+pub(crate) fn reading_time(words: usize) -> usize {
+    words.div_ceil(WORDS_PER_MINUTE).max(1)
+}
+
+/// Extract the prose from some Markdown as plain text, excluding code, math,
+/// HTML and `Tera` directives. Optionally counts code blocks, equations and figures.
+pub(crate) fn extract_prose(
+    markdown: &str,
+    mut stats: Option<&mut PageStats>,
+) -> Result<String, EngineError> {
+    let tera_rgx = Regex::new(r"(?s)\{%.*?%\}|\{\{.*?\}\}")?;
+    let display_math_rgx = Regex::new(r"(?s)\$\$.*?\$\$")?;
+    let inline_math_rgx = Regex::new(r"\$[^$\n]+?\$")?;
+    let markdown = tera_rgx.replace_all(markdown, " ");
+
+    let mut prose = String::new();
+    let mut block = String::new();
+    let mut in_code_block = false;
+
+    // Math can span several text events, so it's stripped once a whole block is collected.
+    let mut flush = |block: &mut String, stats: &mut Option<&mut PageStats>| {
+        let display = display_math_rgx.find_iter(block).count();
+        let without_display = display_math_rgx.replace_all(block, " ");
+        let inline = inline_math_rgx.find_iter(&without_display).count();
+        let without_math = inline_math_rgx.replace_all(&without_display, " ");
+
+        if let Some(stats) = stats.as_deref_mut() {
+            stats.equations += display + inline;
+        }
+        prose.push_str(without_math.trim());
+        prose.push('\n');
+        block.clear();
+    };
+
+    let options = Options::ENABLE_TABLES
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_HEADING_ATTRIBUTES
+        | Options::ENABLE_FOOTNOTES;
+    for event in Parser::new_ext(&markdown, options) {
+        match event {
+            Event::Start(Tag::CodeBlock(_)) => {
+                in_code_block = true;
+                if let Some(stats) = stats.as_deref_mut() {
+                    stats.code_blocks += 1;
+                }
+            }
+            Event::End(TagEnd::CodeBlock) => in_code_block = false,
+            Event::Start(Tag::Image { .. }) => {
+                if let Some(stats) = stats.as_deref_mut() {
+                    stats.figures += 1;
+                }
+            }
+            Event::Text(text) if !in_code_block => block.push_str(&text),
+            Event::SoftBreak | Event::HardBreak => block.push('\n'),
+            Event::End(
+                TagEnd::Paragraph
+                | TagEnd::Heading(_)
+                | TagEnd::Item
+                | TagEnd::TableCell
+                | TagEnd::BlockQuote(_),
+            ) => flush(&mut block, &mut stats),
+            _ => {}
+        }
+    }
+    flush(&mut block, &mut stats);
+
+    Ok(prose.trim().to_string())
+}
+
+/// Collect the [`PageStats`] of every page in the page index, keyed by path.
+pub(crate) fn collect_site_stats(page_index: &[PageMetadata]) -> Vec<(String, PageStats)> {
+    let pages: Vec<(String, PageStats)> = page_index
+        .iter()
+        .filter_map(|page| Some((page.path.clone()?, page.stats.clone()?)))
+        .collect();
+    info!("Collected statistics for {} pages", pages.len());
+
+    pages
+}
+
+/// Print a per-page and site-wide summary of content statistics.
+pub(crate) fn print_site_stats(pages: &[(String, PageStats)]) {
+    println!(
+        "{:<50} {:>7} {:>5} {:>5} {:>5} {:>5}",
+        "page", "words", "min", "code", "math", "figs"
+    );
+    for (path, stats) in pages {
+        println!(
+            "{:<50} {:>7} {:>5} {:>5} {:>5} {:>5}",
+            path,
+            stats.words,
+            stats.reading_time,
+            stats.code_blocks,
+            stats.equations,
+            stats.figures
+        );
+    }
+
+    let total = |f: fn(&PageStats) -> usize| pages.iter().map(|(_, stats)| f(stats)).sum::<usize>();
+    println!(
+        "{:<50} {:>7} {:>5} {:>5} {:>5} {:>5}",
+        format!("total ({} pages)", pages.len()),
+        total(|s| s.words),
+        total(|s| s.reading_time),
+        total(|s| s.code_blocks),
+        total(|s| s.equations),
+        total(|s| s.figures)
+    );
+}
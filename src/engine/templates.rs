@@ -0,0 +1,185 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs,
+};
+
+use log::{error, info};
+use tera::{Template, Tera, ast::Node};
+use walkdir::WalkDir;
+
+use crate::engine::{
+    config::SiteConfig, css::extract_template_styles, error::EngineError, markdown::PageMetadata,
+};
+
+/// Load the site's templates, layered over the theme's, into `Tera`,
+/// and return the CSS of their `<style>` blocks (see [`extract_template_styles`]).
+///
+/// Before anything is rendered, every template must compile, and every template
+/// they `include`, `extends` or `import`, and every template in `pages`' frontmatter,
+/// must exist. Every problem is logged before failing, so they can all be fixed at once.
+pub(crate) fn load_templates(
+    site_config: &SiteConfig,
+    pages: &[PageMetadata],
+) -> Result<(Tera, String), EngineError> {
+    // The site's templates replace the theme's with the same name.
+    let mut files = BTreeMap::new();
+    for dir in site_config.template_dirs() {
+        for entry in WalkDir::new(&dir).into_iter().filter_map(|e| e.ok()) {
+            let path = entry.path();
+            if path.extension().and_then(|s| s.to_str()) != Some("html") {
+                continue;
+            }
+            let name = path
+                .strip_prefix(&dir)?
+                .to_string_lossy()
+                .replace('\\', "/");
+            files.insert(name, path.to_path_buf());
+        }
+    }
+
+    let mut sources = Vec::new();
+    for (name, path) in files {
+        sources.push((name, fs::read_to_string(path)?));
+    }
+
+    let problems = check_templates(&sources, pages);
+    if !problems.is_empty() {
+        for problem in &problems {
+            error!("{problem}");
+        }
+        return Err(EngineError::Template(format!(
+            "{} problem(s) in {}",
+            problems.len(),
+            site_config.templates_path
+        )));
+    }
+
+    let styles = extract_template_styles(&mut sources)?;
+    let mut tera = Tera::default();
+    tera.add_raw_templates(sources)?;
+    info!(
+        "Loaded and validated {} templates",
+        tera.get_template_names().count()
+    );
+
+    Ok((tera, styles))
+}
+
+/// Parse templates, given as `(name, source)` pairs, and check what they and `pages` reference.
+fn check_templates(sources: &[(String, String)], pages: &[PageMetadata]) -> Vec<String> {
+    let names: HashSet<&str> = sources.iter().map(|(name, _)| name.as_str()).collect();
+    let mut problems = Vec::new();
+
+    for (name, source) in sources {
+        let template = match Template::new(name, None, source) {
+            Ok(template) => template,
+            Err(e) => {
+                let message = match std::error::Error::source(&e) {
+                    Some(source) => format!("{e}: {source}"),
+                    None => e.to_string(),
+                };
+                problems.push(format!("{name}: {message}"));
+                continue;
+            }
+        };
+
+        if let Some(parent) = &template.parent
+            && !names.contains(parent.as_str())
+        {
+            problems.push(format!("{name}: extends missing template `{parent}`"));
+        }
+        for (file, _) in &template.imported_macro_files {
+            if !names.contains(file.as_str()) {
+                problems.push(format!("{name}: imports missing template `{file}`"));
+            }
+        }
+
+        let mut includes = Vec::new();
+        collect_includes(&template.ast, &mut includes);
+        for include in includes {
+            if !include.iter().any(|file| names.contains(file.as_str())) {
+                problems.push(format!(
+                    "{name}: includes missing template `{}`",
+                    include.join("` or `")
+                ));
+            }
+        }
+    }
+
+    for page in pages {
+        if let Some(template) = &page.template
+            && !names.contains(template.as_str())
+        {
+            problems.push(format!(
+                "{}: frontmatter uses missing template `{template}`",
+                page.path.as_deref().unwrap_or(&page.title)
+            ));
+        }
+    }
+
+    problems
+}
+
+/// Collect the candidates of every `{% include %}` in `nodes`, except `ignore missing` ones.
+fn collect_includes<'a>(nodes: &'a [Node], includes: &mut Vec<&'a [String]>) {
+    for node in nodes {
+        match node {
+            Node::Include(_, files, ignore_missing) if !ignore_missing => includes.push(files),
+            Node::MacroDefinition(_, definition, _) => collect_includes(&definition.body, includes),
+            Node::FilterSection(_, section, _) => collect_includes(&section.body, includes),
+            Node::Block(_, block, _) => collect_includes(&block.body, includes),
+            Node::Forloop(_, forloop, _) => {
+                collect_includes(&forloop.body, includes);
+                if let Some(body) = &forloop.empty_body {
+                    collect_includes(body, includes);
+                }
+            }
+            Node::If(conditions, _) => {
+                for (_, _, body) in &conditions.conditions {
+                    collect_includes(body, includes);
+                }
+                if let Some((_, body)) = &conditions.otherwise {
+                    collect_includes(body, includes);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_templates() {
+        let sources = [
+            ("base.html", "{% block content %}{% endblock %}"),
+            (
+                "page.html",
+                "{% extends \"base.html\" %}{% block content %}{% if x %}{% include \"footer.html\" %}{% endif %}{% endblock %}",
+            ),
+            (
+                "post.html",
+                "{% extends \"layout.html\" %}{% import \"macros.html\" as m %}{% include \"nav.html\" ignore missing %}",
+            ),
+            ("broken.html", "{% if %}"),
+        ]
+        .map(|(name, source)| (name.to_string(), source.to_string()));
+        let pages = [PageMetadata {
+            path: Some("/about/".to_string()),
+            template: Some("about.html".to_string()),
+            ..Default::default()
+        }];
+
+        let problems = check_templates(&sources, &pages);
+        assert_eq!(problems.len(), 5);
+        assert!(problems.contains(&"page.html: includes missing template `footer.html`".into()));
+        assert!(problems.contains(&"post.html: extends missing template `layout.html`".into()));
+        assert!(problems.contains(&"post.html: imports missing template `macros.html`".into()));
+        assert!(problems.iter().any(|p| p.starts_with("broken.html: ")));
+        assert!(
+            problems.contains(&"/about/: frontmatter uses missing template `about.html`".into())
+        );
+    }
+}
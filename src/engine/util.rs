@@ -1,6 +1,6 @@
-use regex::Regex;
+use std::collections::HashSet;
 
-use crate::EngineError;
+use sha2::{Digest, Sha256};
 
 /// Strip leading whitespaces from HTML.
 pub(crate) fn strip_leading_whitespace_from_html(content: &str) -> String {
@@ -18,7 +18,7 @@ pub(crate) fn strip_leading_whitespace_from_html(content: &str) -> String {
 }
 
 /// Convert text into to a URL-safe slug.
-fn slugify(text: &str) -> String {
+pub(crate) fn slugify(text: &str) -> String {
     text.to_lowercase()
         .chars()
         .filter_map(|c| {
@@ -38,10 +38,9 @@ fn slugify(text: &str) -> String {
 }
 
 /// Generate a unique ID for a string. Handles duplicates by appending an idx.
-pub(crate) fn generate_unique_id(
-    text: &str,
-    used_ids: &mut std::collections::HashSet<String>,
-) -> String {
+///
+/// This is the single slugifier for headings, shared by the ToC and the heading IDs.
+pub(crate) fn generate_unique_id(text: &str, used_ids: &mut HashSet<String>) -> String {
     let base_slug = slugify(text);
 
     if !used_ids.contains(&base_slug) {
@@ -60,25 +59,15 @@ pub(crate) fn generate_unique_id(
     }
 }
 
-/// Inject heading IDs into already-generated HTML
-pub(crate) fn inject_heading_ids_into_html(html: &str) -> Result<String, EngineError> {
-    use std::collections::HashSet;
-
-    let mut result = html.to_string();
-    let mut used_ids = HashSet::new();
-
-    // Regex to match heading tags
-    let heading_rgx = Regex::new(r"<(h[1-6])>([^<]+)</h[1-6]>")?;
-
-    result = heading_rgx
-        .replace_all(&result, |caps: &regex::Captures| {
-            let tag = &caps[1];
-            let text = &caps[2];
-            let id = generate_unique_id(text, &mut used_ids);
-
-            format!("<{tag} id=\"{id}\">{text}</{tag}>")
-        })
-        .to_string();
+/// Convert a page's build path (e.g. `/blog/post/index.html`) into a clean URL (`/blog/post/`).
+pub(crate) fn clean_url(path: &str) -> String {
+    match path.strip_suffix("index.html") {
+        Some(dir) => dir.to_string(),
+        None => path.to_string(),
+    }
+}
 
-    Ok(result)
+/// Compute the hex-encoded SHA-256 hash of some content.
+pub(crate) fn content_hash(content: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(content))
 }
@@ -1,247 +0,0 @@
-pub(crate) const QUOTES: &[(&str, &str)] = &[
-    (
-        "If you want to build a ship, don't drum up the men to gather wood, divide the work, and give orders. Instead, teach them to yearn for the vast and endless sea.",
-        "Antoine de Saint-Exupéry",
-    ),
-    (
-        "If I had more time, I would have written a shorter letter.",
-        "Blaise Pascal",
-    ),
-    (
-        "Learn to live on lentils and you will not have to be subservient to the king.",
-        "Diogenes of Sinope",
-    ),
-    (
-        "Convinced myself, I seek not to convince.",
-        "Edgar Allan Poe",
-    ),
-    (
-        "Cypherpunks write code. We know that someone has to write software to defend privacy, and since we can't get privacy unless we all do, we're going to write it. We publish our code so that our fellow Cypherpunks may practice and play with it. Our code is free for all to use, worldwide.",
-        "Eric Hughes",
-    ),
-    (
-        "Once men turned their thinking over to machines in the hope that this would set them free. But that only permitted other men with machines to enslave them.",
-        "Frank Herbert",
-    ),
-    (
-        "When plunder is abetted by the law, it does not fear your courts, your gendarmes, and your prisons. Rather, it may call upon them for help.",
-        "Frederic Bastiat",
-    ),
-    (
-        "I don't believe we shall ever have a good money again before we take the thing out of the hands of government, that is, we can't take it violently out of the hands of government, all we can do is by some sly roundabout way introduce something that they can't stop.",
-        "Friedrich Hayek",
-    ),
-    (
-        "Lying is a delightful thing for it leads to the truth.",
-        "Fyodor Dostoyevsky",
-    ),
-    (
-        "To go wrong in one's own way is better than to go right in someone else's.",
-        "Fyodor Dostoyevsky",
-    ),
-    (
-        "Right or wrong, it's very pleasant to break something from time to time.",
-        "Fyodor Dostoyevsky",
-    ),
-    (
-        "So long as man remains free he strives for nothing so incessantly and so painfully as to find someone to worship.",
-        "Fyodor Dostoyevsky",
-    ),
-    (
-        "There is no subject so old that nothing new can be said about it.",
-        "Fyodor Dostoyevsky",
-    ),
-    (
-        "Nothing is more seductive to a man than his freedom of conscience, but nothing is a greater cause of suffering.",
-        "Fyodor Dostoyevsky",
-    ),
-    (
-        "Any fool can make a rule. And any fool will mind it.",
-        "Henry David Thoreau",
-    ),
-    (
-        "The cost of a thing is the amount of what I will call life which is required to be exchanged for it, immediately or in the long run.",
-        "Henry David Thoreau",
-    ),
-    (
-        "A man is rich in proportion to the number of things which he can afford to let alone.",
-        "Henry David Thoreau",
-    ),
-    (
-        "A truly good book…teaches me better than to read it. I must soon lay it down and commence living on its hint.",
-        "Henry David Thoreau",
-    ),
-    (
-        "I went to the woods because I wished to live deliberately, to front only the essential facts of life, and see if I could not learn what it had to teach, and not, when I came to die, discover that I had not lived.",
-        "Henry David Thoreau",
-    ),
-    (
-        "I know of no more encouraging fact than the unquestionable ability of man to elevate his life by conscious endeavour.",
-        "Henry David Thoreau",
-    ),
-    (
-        "Any book worth banning is a book worth reading.",
-        "Isaac Asimov",
-    ),
-    (
-        "Self-education is, I firmly believe, the only kind of education there is.",
-        "Isaac Asimov",
-    ),
-    (
-        "If I have been able to see further, it was only because I stood on the shoulders of giants.",
-        "Isaac Newton",
-    ),
-    (
-        "If we desire respect for the law, we must first make the law respectable.",
-        "Louis Brandeis",
-    ),
-    (
-        "It ain't what you don't know that gets you into trouble. It's what you know for sure that just ain't so.",
-        "Mark Twain",
-    ),
-    (
-        "The ultimate aim of martial arts is not having to use them.",
-        "Miyamoto Musashi",
-    ),
-    (
-        "I dreamt of wordly success once.",
-        "Miyamoto Musashi"
-    ),
-    (
-        "Unfortunately, no one can be told what the Matrix is. You have to see it for yourself.",
-        "Morpheus",
-    ),
-    (
-        "There are only two tragedies in life: one is not getting what you want, and the other is getting it.",
-        "Oscar Wilde",
-    ),
-    (
-        "I cannot remember the books I've read any more than the meals I have eaten; even so, they have made me.",
-        "Ralph Waldo Emerson",
-    ),
-    (
-        "Machinery is aggressive. The weaver becomes a web, the machinist a machine. If you do not use tools, they use you.",
-        "Ralph Waldo Emerson",
-    ),
-    (
-        "Two roads diverged in a wood, and I— I took the one less traveled by, And that has made all the difference.",
-        "Robert Frost",
-    ),
-    (
-        "If you don't believe me or don't get it, I don't have time to try to convince you, sorry.",
-        "Satoshi Nakamoto",
-    ),
-    (
-        "We can't have full knowledge all at once. We must start by believing; then afterwards we may be led on to master the evidence for ourselves.",
-        "Thomas Aquinas",
-    ),
-    (
-        "I have never gone amongst men without coming back less a man.",
-        "Thomas à Kempis",
-    ),
-    (
-        "You can only describe Bitcoin, you can never define Bitcoin.",
-        "dergigi",
-    ),
-    (
-        "Matters of great concern should be treated lightly.” Master Ittei commented, “Matters of small concern should be treated seriously.",
-        "Yamamoto Tsunetomo",
-    ),
-    (
-        "In the modern world, you are defined not by what you say no to; but what you say yes to.",
-        "George Hotz",
-    ),
-    (
-        "First step to not being a noob is to not think like a noob. To stop being a noob, stop engaging with the system. Don’t let validation define you. Build systems that don’t involve social capital, legitimacy, or who you are—just pure technicality. Bitcoin’s a great example. It doesn’t care where its coins come from. It’s just technicality, not artificial barriers of control.",
-        "George Hotz",
-    ),
-    (
-        "The Path is not to be taken lightly, but our steps must be wheightless.",
-        "Unknown Author",
-    ),
-    (
-        "When you start to walk on the way, the way appears.",
-        "Rumi",
-    ),
-    (
-        "Get busy livin'... Or get busy dyin'",
-        "Andy Dufresne"
-    ),
-    (
-        "Forget that... there are places in this world that aren't made out of stone. That there's something, inside... that they can't get to, that they can't touch. That's yours.",
-        "Andy Dufresne",
-    ),
-    (
-        "There is something to be learned from a rainstorm. When meeting with a sudden shower, you try not to get wet and run quickly along the road. By doing such things as passing under the eaves of houses, you still get wet. When you are resolved from the beginning, you will not be perplexed, though you will still get the same soaking.",
-        "Yamamoto Tsunetomo",
-    ),
-    (
-        "If privacy is outlawed, only outlaws will have privacy.",
-        "Phil Zimmermann",
-    ),
-    (
-        "There are decades where nothing happens; and there are weeks where decades happen.",
-        "Vladmir Ilyich Lenin",
-    ),
-    (
-        "Those who would give up essential Liberty, to purchase a little temporary Safety, deserve neither Liberty nor Safety.",
-        "Benjamin Franklin",
-    ),
-    (
-        "The Net interprets censorship as damage and routes around it.",
-        "Jhon Gilmore",
-    ),
-    (
-        "We can define intelligence as the ability to accurately model reality.",
-        "Luis Schwab",
-    ),
-    (
-        "Bitcoin is not a refactor; Bitcoin is a rewrite",
-        "Adam Jonas",
-    ),
-    (
-        "If you treat an individual as he is, he will remain how he is. But if you treat him as if he were what he ought to be and could be, he will become what he ought to be and could be.",
-        "Johann Wolfgang von Goethe",
-    ),
-    (
-        "One Seed to rule them all,
-        One Key to find them,
-        One Path to bring them all,
-        And in cryptography bind them.",
-        "Sauron",
-    ),
-    (
-        "Because if in fact, you can't crack that at all, government can't get in, then everybody's walking around with a Swiss bank account in their pocket.",
-        "Barack Obama",
-    ),
-    (
-        "How have you done any of this? We have to go back.
-
-        It's too late for that. We're closer to the other side.
-
-        What other side? You wanna drown us both?
-
-        You wanna know how I did it? This is how I did it Anton. I never saved anything for the swim back.",
-        "Anton and Vincent Freeman, in GATTACA"
-    ),
-    (
-        "Talk is cheap, send patches",
-        "FFmpeg"
-    ),
-    (
-        "Not having heard something is not as good as having heard it; having heard it is not as good as having seen it; having seen it is not as good as knowing it; knowing it is not as good as putting it into practice.",
-        "Xunzi"
-    ),
-    (
-        "A King may move a man, a father may claim a son, but remember that even when those who move you be Kings, or men of power, your soul is in your keeping alone. When you stand before God, you cannot say, \"But I was told by others to do thus.\" Or that, \"Virtue was not convenient at the time.\" This will not suffice. Remember that.",
-        "King Baldwin IV"
-    ),
-    (
-        "No matter how isolated you are and how lonely you feel, if you do your work truly and conscientiously, unknown allies will come and seek you.",
-        "Carl Jung"
-    ),
-    (
-        "I am an old man and have known a great many troubles, but most of them have never happened.",
-        "Mark Twain"
-    )
-];
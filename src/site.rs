@@ -1,10 +1,12 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     env, fs,
     path::{Path, PathBuf},
     process,
 };
 
+use chrono::{Datelike, Local, NaiveDate};
+use clap::{Parser, Subcommand};
 use env_logger::Env;
 use log::{error, info};
 use serde::{Deserialize, Serialize};
@@ -13,26 +15,76 @@ use tera::{Context, Tera};
 use walkdir::WalkDir;
 
 mod engine;
-mod quotes;
 
 use engine::{
+    assets::{copy_static_files, write_manifest},
     config::{SiteConfig, parse_config_file},
+    csp::write_security_headers,
+    css::bundle_css,
+    data::{LoadData, load_data_dir},
     error::EngineError,
-    markdown::{PageMetadata, process_md_file},
+    functions::register_functions,
+    images::ImagePipeline,
+    markdown::{
+        PageMetadata, PageResources, extract_summary, process_md_file, render_virtual_page,
+    },
+    quotes::{Quote, QuoteBook, load_quotes},
+    redirect::{collect_redirects, write_redirect_stubs, write_server_snippet},
+    search::write_search_index,
+    seo::is_blog_post,
+    social::SocialPreviews,
+    stats::{collect_site_stats, compute_stats, print_site_stats},
+    templates::load_templates,
 };
-use quotes::QUOTES;
 
-/// The file where site-wide definitions must be declared.
-/// The path is relative to Cargo project's root.
+/// The file where site-wide definitions must be declared, by default.
+/// The path is relative to the working directory.
 const CONFIG_FILE: &str = "config.toml";
 
+/// The static site engine for luisschwab.net.
+#[derive(Parser)]
+struct Cli {
+    /// The configuration file. Every path in it is relative to it.
+    #[arg(long, global = true, default_value = CONFIG_FILE)]
+    config: PathBuf,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Build the site into the build directory (the default).
+    Build,
+    /// Print word counts, reading times and other content statistics.
+    Stats,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub(crate) struct TagIndex {
     pub(crate) tag: String,
     pub(crate) posts: Vec<PageMetadata>,
 }
 
+/// The blog posts of a year, grouped by month.
+#[derive(Clone, Debug, Serialize)]
+struct ArchiveYear {
+    year: i32,
+    months: Vec<ArchiveMonth>,
+}
+
+/// The blog posts of a month, newest first.
+#[derive(Clone, Debug, Serialize)]
+struct ArchiveMonth {
+    /// The month's anchor, like `2026-03`.
+    id: String,
+    /// The month's name, like `March`.
+    name: String,
+    posts: Vec<PageMetadata>,
+}
+
 fn main() -> Result<(), EngineError> {
+    let cli = Cli::parse();
+
     // Initialize environment logger.
     env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
 
@@ -48,7 +100,7 @@ fn main() -> Result<(), EngineError> {
         }
     };
 
-    let config_path: PathBuf = PathBuf::from(CONFIG_FILE);
+    let config_path = cli.config;
     if !config_path.exists() {
         error!(
             "Failed to read a configuration file at {}",
@@ -58,38 +110,51 @@ fn main() -> Result<(), EngineError> {
     }
     let config: SiteConfig = parse_config_file(config_path)?;
 
+    match cli.command {
+        Some(Command::Stats) => {
+            let stats = collect_site_stats(&build_page_index(&config, prod)?);
+            print_site_stats(&stats);
+            Ok(())
+        }
+        Some(Command::Build) | None => build_site(&config, prod),
+    }
+}
+
+/// Render every page in the content directory into the build directory.
+fn build_site(config: &SiteConfig, prod: bool) -> Result<(), EngineError> {
     // Content (src) and Build (dst) directories.
     let build_dir = &config.build_path;
     let content_dir = &config.content_path;
 
-    // Create a Tera object and context.
-    let mut tera = Tera::new("src/templates/**/*.html")?;
+    // Build an index of every page, for the engine's functions.
+    let page_index = build_page_index(config, prod)?;
+    // Only the indexed pages are checked and rendered, which leaves out drafts in production.
+    let page_sources: HashSet<PathBuf> = page_index
+        .iter()
+        .filter_map(|page| page.source.clone())
+        .collect();
+
+    // Load and check every template, and those pages ask for, before rendering anything.
+    let (mut tera, template_css) = load_templates(config, &page_index)?;
+    tera.autoescape_on(vec![".html", ".htm", ".xml", ".svg"]);
     let mut tera_ctx = Context::new();
     info!("Succesfully built a Tera context");
 
-    // Build a quote JSON array from `QUOTES`.
-    let quotes_json = json!(
-        QUOTES
-            .iter()
-            .map(|(text, author)| {
-                json!({"text": text.replace("\n", "<br/>"), "author": author.replace("\n", "<br/>")})
-            })
-            .collect::<Vec<_>>()
-    );
+    // Expose data files to templates, both preloaded and on demand.
+    tera_ctx.insert("data", &load_data_dir(&config.data_path)?);
+    tera.register_function("load_data", LoadData::new(&config.data_path));
+    info!("Inserted data files into Tera's context");
+
+    // Build a quote JSON array from the quotes data file.
+    let build_date = Local::now().date_naive();
+    let quotes = QuoteBook::new(load_quotes(Path::new(&config.quotes_path))?, build_date);
+    let quotes_json = json!(quotes.quotes.iter().map(Quote::to_json).collect::<Vec<_>>());
     tera_ctx.insert("quotes_json", &quotes_json.to_string());
+    tera_ctx.insert("quote_mode", &config.quote_mode);
     info!("Inserted quotes JSON array into Tera's context");
 
-    // Display the lats quote from `QUOTES`, if the browser has "JavaScripto" disabled.
-    let fallback_quote: (&str, &str) = QUOTES[QUOTES.len() - 1];
-    let fallback_quote = (
-        fallback_quote.0.replace("\n", "<br/>"),
-        fallback_quote.1.replace("\n", "<br/>"),
-    );
-    tera_ctx.insert("quote_text", &fallback_quote.0);
-    tera_ctx.insert("quote_author", &fallback_quote.1);
-
     // Build an index of blog posts to be inserted to the context later.
-    let blog_index = build_blog_index(content_dir, prod)?;
+    let blog_index = build_blog_index(&page_index);
     tera_ctx.insert("blog_index", &blog_index);
     info!("Inserted blog post index into Tera's context");
 
@@ -97,20 +162,51 @@ fn main() -> Result<(), EngineError> {
     tera_ctx.insert("blog_tag_index", &blog_tag_index);
     info!("Inserted blog tag index into Tera's context");
 
+    // The `/search/` page only exists if search is configured.
+    tera_ctx.insert("search_enabled", &config.search.is_some());
+
+    // Copy static files and bundle stylesheets first, so templates can look up their URLs.
+    let mut assets = copy_static_files(config)?;
+    let css = bundle_css(config, &template_css, &mut assets)?;
+    write_manifest(config, &assets)?;
+
+    // Index pages for the search page, if configured, before templates take the index.
+    write_search_index(config, &page_index)?;
+
+    register_functions(&mut tera, config, page_index, assets);
+    info!("Registered the engine's Tera functions and filters");
+
+    // Images are resized and re-encoded as they are found in pages.
+    let images = ImagePipeline::new(config.images.clone())?;
+    let previews = SocialPreviews::new(config, &mut tera)?;
+    let resources = PageResources {
+        images: &images,
+        previews: &previews,
+        quotes: &quotes,
+        css: &css,
+    };
+
     // Process file contents.
+    let mut pages: Vec<PageMetadata> = Vec::new();
     for entry in WalkDir::new(content_dir).into_iter().filter_map(|e| e.ok()) {
         let file_path = entry.path();
 
         if let Some(extension) = file_path.extension().and_then(|s| s.to_str()) {
             if extension == "md" {
-                process_md_file(
+                if !page_sources.contains(file_path) {
+                    info!("Skipping draft {}", file_path.display());
+                    continue;
+                }
+                let metadata = process_md_file(
                     &mut tera,
                     &mut tera_ctx,
-                    &config,
+                    config,
+                    &resources,
                     file_path,
                     content_dir,
                     build_dir,
                 )?;
+                pages.push(metadata);
             } else {
                 // Copy assets from the content directory.
                 copy_asset_file(file_path, content_dir, build_dir)?;
@@ -118,47 +214,189 @@ fn main() -> Result<(), EngineError> {
         }
     }
 
+    // Generate pages that have no Markdown source.
+    pages.push(build_quotes_page(
+        &tera, &tera_ctx, config, &resources, build_date,
+    )?);
+    pages.push(build_archive_page(
+        &tera,
+        &tera_ctx,
+        config,
+        &resources,
+        &blog_index,
+        build_date,
+    )?);
+    if config.search.is_some() {
+        pages.push(build_search_page(
+            &tera, &tera_ctx, config, &resources, build_date,
+        )?);
+    }
+
+    // Generate redirect stubs for moved content, and keep the server config in sync.
+    let redirects = collect_redirects(config, &pages)?;
+    write_redirect_stubs(config, &redirects, build_dir)?;
+    write_server_snippet(config, &redirects, build_dir)?;
+
+    // Allow every page's inline scripts and styles in the server's security headers.
+    write_security_headers(config, build_dir)?;
+
     Ok(())
 }
 
-/// Build an index of the blog posts.
-fn build_blog_index(content_dir: &String, prod: bool) -> Result<Vec<PageMetadata>, EngineError> {
-    let mut blog_index = Vec::new();
+/// Generate the `/quotes/` page, listing every quote grouped by author.
+fn build_quotes_page(
+    tera: &Tera,
+    tera_ctx: &Context,
+    config: &SiteConfig,
+    resources: &PageResources,
+    build_date: NaiveDate,
+) -> Result<PageMetadata, EngineError> {
+    let mut quotes_ctx = tera_ctx.clone();
+    quotes_ctx.insert("quote_groups", &resources.quotes.by_author());
+
+    let metadata = PageMetadata {
+        template: Some("base.html".to_string()),
+        title: "Quotes".to_string(),
+        description: "Every quote shown in this site's footer".to_string(),
+        date: build_date,
+        path: Some("/quotes/index.html".to_string()),
+        ..Default::default()
+    };
+
+    render_virtual_page(
+        tera,
+        &quotes_ctx,
+        config,
+        resources,
+        metadata,
+        "quotes.html",
+        &config.build_path,
+    )
+}
+
+/// Generate the `/blog/archive/` page, listing every blog post by year and month.
+fn build_archive_page(
+    tera: &Tera,
+    tera_ctx: &Context,
+    config: &SiteConfig,
+    resources: &PageResources,
+    blog_index: &[PageMetadata],
+    build_date: NaiveDate,
+) -> Result<PageMetadata, EngineError> {
+    let mut archive_ctx = tera_ctx.clone();
+    archive_ctx.insert("archive", &build_archive_index(blog_index));
+
+    let metadata = PageMetadata {
+        template: Some("base.html".to_string()),
+        title: "Blog Archive".to_string(),
+        description: "Every blog post, by year, month and tag".to_string(),
+        date: build_date,
+        path: Some("/blog/archive/index.html".to_string()),
+        listing: Some(true),
+        ..Default::default()
+    };
+
+    render_virtual_page(
+        tera,
+        &archive_ctx,
+        config,
+        resources,
+        metadata,
+        "blog/archive.html",
+        &config.build_path,
+    )
+}
+
+/// Generate the `/search/` page, which searches the search index in the browser.
+fn build_search_page(
+    tera: &Tera,
+    tera_ctx: &Context,
+    config: &SiteConfig,
+    resources: &PageResources,
+    build_date: NaiveDate,
+) -> Result<PageMetadata, EngineError> {
+    let metadata = PageMetadata {
+        template: Some("base.html".to_string()),
+        title: "Search".to_string(),
+        description: "Search every blog post".to_string(),
+        date: build_date,
+        path: Some("/search/index.html".to_string()),
+        ..Default::default()
+    };
+
+    render_virtual_page(
+        tera,
+        tera_ctx,
+        config,
+        resources,
+        metadata,
+        "search.html",
+        &config.build_path,
+    )
+}
+
+/// Build an index of every page, for looking up other pages from templates.
+fn build_page_index(config: &SiteConfig, prod: bool) -> Result<Vec<PageMetadata>, EngineError> {
+    let content_dir = &config.content_path;
+    let mut page_index = Vec::new();
     for entry in WalkDir::new(content_dir).into_iter().filter_map(|e| e.ok()) {
         let file_path = entry.path();
-        if file_path.extension().and_then(|s| s.to_str()) == Some("md")
-            && file_path.to_str().unwrap().contains("/blog/")
-        {
-            // Parse the frontmatter.
-            let content = std::fs::read_to_string(file_path)?;
-            if let Some(extracted) = matter::matter(&content) {
-                let mut metadata: PageMetadata = toml::from_str(&extracted.0)?;
-                // Don't include a draft page in the build output
-                // if the `PROD=true` enviromnet variable is set.
-                #[allow(clippy::bool_comparison)]
-                if metadata.draft == Some(true) && prod == true {
-                    continue;
-                }
-                let rel_path = file_path.strip_prefix(content_dir)?;
-
-                // Generate clean URLs.
-                let path = if file_path.file_name().unwrap() == "index.md" {
-                    // For `index.md` files, use the parent directory path.
-                    format!("/{}/", rel_path.parent().unwrap().display())
-                } else {
-                    // For files that are not `index.md`, use their names.
-                    format!("/{}", rel_path.with_extension("html").display())
-                };
-                metadata.path = Some(path);
-
-                let is_blog_index = rel_path.to_str().unwrap() == "blog/index.md"
-                    || rel_path.to_str().unwrap() == "blog/tags/index.md";
-                if !is_blog_index {
-                    blog_index.push(metadata);
-                }
+        if file_path.extension().and_then(|s| s.to_str()) != Some("md") {
+            continue;
+        }
+
+        // Parse the frontmatter.
+        let content = std::fs::read_to_string(file_path)?;
+        if let Some(extracted) = matter::matter(&content) {
+            let mut metadata: PageMetadata = toml::from_str(&extracted.0)?;
+            // Don't include a draft page in the build output
+            // if the `PROD=true` enviromnet variable is set.
+            #[allow(clippy::bool_comparison)]
+            if metadata.draft == Some(true) && prod == true {
+                continue;
             }
+            metadata.stats = Some(compute_stats(&extracted.1)?);
+            metadata.summary = extract_summary(&extracted.1, config.summary_paragraphs)?;
+            let rel_path = file_path.strip_prefix(content_dir)?;
+
+            // Generate clean URLs.
+            let path = if file_path.file_name().unwrap() == "index.md" {
+                // For `index.md` files, use the parent directory path.
+                match rel_path.parent() {
+                    Some(parent) if !parent.as_os_str().is_empty() => {
+                        format!("/{}/", parent.display())
+                    }
+                    _ => "/".to_string(),
+                }
+            } else {
+                // For files that are not `index.md`, use their names.
+                format!("/{}", rel_path.with_extension("html").display())
+            };
+            metadata.path = Some(path);
+            metadata.source = Some(file_path.to_path_buf());
+
+            page_index.push(metadata);
+        } else {
+            return Err(EngineError::NoMatter);
         }
     }
+    page_index.sort_by(|a, b| a.path.cmp(&b.path));
+
+    Ok(page_index)
+}
+
+/// Build an index of the blog posts.
+fn build_blog_index(page_index: &[PageMetadata]) -> Vec<PageMetadata> {
+    let mut blog_index: Vec<PageMetadata> = page_index
+        .iter()
+        .filter(|page| {
+            page.path
+                .as_deref()
+                .is_some_and(|path| is_blog_post(page, path))
+        })
+        .cloned()
+        .collect();
+
     // Insert blog post metadata to Tera's context, sorted by date in descending order.
     blog_index.sort_by(|a, b| {
         let date_a = a.date;
@@ -166,7 +404,7 @@ fn build_blog_index(content_dir: &String, prod: bool) -> Result<Vec<PageMetadata
         date_b.cmp(&date_a)
     });
 
-    Ok(blog_index)
+    blog_index
 }
 
 /// Build an index of blog posts organized by tags.
@@ -187,7 +425,7 @@ fn build_tag_index(blog_posts: &[PageMetadata]) -> Vec<TagIndex> {
         .into_iter()
         .map(|(tag, mut posts)| {
             // Sort posts within each tag by date (newest first)
-            posts.sort_by(|a, b| b.date.cmp(&a.date));
+            posts.sort_by_key(|post| std::cmp::Reverse(post.date));
             TagIndex { tag, posts }
         })
         .collect();
@@ -198,6 +436,35 @@ fn build_tag_index(blog_posts: &[PageMetadata]) -> Vec<TagIndex> {
     tag_index
 }
 
+/// Group blog posts, sorted newest first, by year and month.
+fn build_archive_index(blog_posts: &[PageMetadata]) -> Vec<ArchiveYear> {
+    let mut archive: Vec<ArchiveYear> = Vec::new();
+    for post in blog_posts {
+        if archive
+            .last()
+            .is_none_or(|year| year.year != post.date.year())
+        {
+            archive.push(ArchiveYear {
+                year: post.date.year(),
+                months: Vec::new(),
+            });
+        }
+        let months = &mut archive.last_mut().unwrap().months;
+
+        let id = post.date.format("%Y-%m").to_string();
+        if months.last().is_none_or(|month| month.id != id) {
+            months.push(ArchiveMonth {
+                id,
+                name: post.date.format("%B").to_string(),
+                posts: Vec::new(),
+            });
+        }
+        months.last_mut().unwrap().posts.push(post.clone());
+    }
+
+    archive
+}
+
 // Copy asset files (images, etc.) to the build directory while preserving structure.
 fn copy_asset_file(
     file_path: &Path,